@@ -0,0 +1,38 @@
+pub use sea_orm_migration::prelude::*;
+
+mod m20250429_122313_user;
+mod m20250429_123628_post;
+mod m20250514_025642_file_upload;
+mod m20250603_112154_book;
+mod m20250606_124608_category;
+mod m20261018_000001_create_refresh_token_table;
+mod m20261018_000002_add_role_to_user;
+mod m20261018_000003_create_password_reset_token_table;
+mod m20261018_000004_add_totp_to_user;
+mod m20261018_000005_create_recovery_code_table;
+mod m20261018_000006_create_api_key_table;
+mod m20261018_000007_add_search_vector_to_post;
+mod m20261018_000008_create_post_attachment_table;
+
+pub struct Migrator;
+
+#[async_trait::async_trait]
+impl MigratorTrait for Migrator {
+    fn migrations() -> Vec<Box<dyn MigrationTrait>> {
+        vec![
+            Box::new(m20250429_122313_user::Migration),
+            Box::new(m20250429_123628_post::Migration),
+            Box::new(m20250514_025642_file_upload::Migration),
+            Box::new(m20250603_112154_book::Migration),
+            Box::new(m20250606_124608_category::Migration),
+            Box::new(m20261018_000001_create_refresh_token_table::Migration),
+            Box::new(m20261018_000002_add_role_to_user::Migration),
+            Box::new(m20261018_000003_create_password_reset_token_table::Migration),
+            Box::new(m20261018_000004_add_totp_to_user::Migration),
+            Box::new(m20261018_000005_create_recovery_code_table::Migration),
+            Box::new(m20261018_000006_create_api_key_table::Migration),
+            Box::new(m20261018_000007_add_search_vector_to_post::Migration),
+            Box::new(m20261018_000008_create_post_attachment_table::Migration),
+        ]
+    }
+}
@@ -0,0 +1,71 @@
+use sea_orm_migration::{prelude::*, schema::*};
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .create_table(
+                Table::create()
+                    .table(RefreshToken::Table)
+                    .if_not_exists()
+                    .col(uuid(RefreshToken::Id).primary_key())
+                    .col(uuid(RefreshToken::UserId))
+                    .col(uuid(RefreshToken::FamilyId))
+                    .col(string(RefreshToken::TokenHash).unique_key())
+                    .col(timestamp_with_time_zone(RefreshToken::ExpiresAt))
+                    .col(timestamp_with_time_zone_null(RefreshToken::UsedAt))
+                    .col(timestamp_with_time_zone_null(RefreshToken::RevokedAt))
+                    .col(
+                        timestamp_with_time_zone(RefreshToken::CreatedAt)
+                            .default(Expr::current_timestamp()),
+                    )
+                    .foreign_key(
+                        ForeignKey::create()
+                            .name("fk-refresh_token-user_id")
+                            .from(RefreshToken::Table, RefreshToken::UserId)
+                            .to(User::Table, User::Id)
+                            .on_delete(ForeignKeyAction::Cascade),
+                    )
+                    .to_owned(),
+            )
+            .await?;
+
+        manager
+            .create_index(
+                Index::create()
+                    .name("idx-refresh_token-family_id")
+                    .table(RefreshToken::Table)
+                    .col(RefreshToken::FamilyId)
+                    .to_owned(),
+            )
+            .await
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .drop_table(Table::drop().table(RefreshToken::Table).to_owned())
+            .await
+    }
+}
+
+#[derive(DeriveIden)]
+enum RefreshToken {
+    Table,
+    Id,
+    UserId,
+    FamilyId,
+    TokenHash,
+    ExpiresAt,
+    UsedAt,
+    RevokedAt,
+    CreatedAt,
+}
+
+#[derive(DeriveIden)]
+enum User {
+    Table,
+    Id,
+}
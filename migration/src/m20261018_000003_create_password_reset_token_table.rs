@@ -0,0 +1,57 @@
+use sea_orm_migration::{prelude::*, schema::*};
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .create_table(
+                Table::create()
+                    .table(PasswordResetToken::Table)
+                    .if_not_exists()
+                    .col(uuid(PasswordResetToken::Id).primary_key())
+                    .col(uuid(PasswordResetToken::UserId))
+                    .col(string(PasswordResetToken::TokenHash).unique_key())
+                    .col(timestamp_with_time_zone(PasswordResetToken::ExpiresAt))
+                    .col(timestamp_with_time_zone_null(PasswordResetToken::UsedAt))
+                    .col(
+                        timestamp_with_time_zone(PasswordResetToken::CreatedAt)
+                            .default(Expr::current_timestamp()),
+                    )
+                    .foreign_key(
+                        ForeignKey::create()
+                            .name("fk-password_reset_token-user_id")
+                            .from(PasswordResetToken::Table, PasswordResetToken::UserId)
+                            .to(User::Table, User::Id)
+                            .on_delete(ForeignKeyAction::Cascade),
+                    )
+                    .to_owned(),
+            )
+            .await
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .drop_table(Table::drop().table(PasswordResetToken::Table).to_owned())
+            .await
+    }
+}
+
+#[derive(DeriveIden)]
+enum PasswordResetToken {
+    Table,
+    Id,
+    UserId,
+    TokenHash,
+    ExpiresAt,
+    UsedAt,
+    CreatedAt,
+}
+
+#[derive(DeriveIden)]
+enum User {
+    Table,
+    Id,
+}
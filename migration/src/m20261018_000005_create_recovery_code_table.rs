@@ -0,0 +1,66 @@
+use sea_orm_migration::{prelude::*, schema::*};
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .create_table(
+                Table::create()
+                    .table(RecoveryCode::Table)
+                    .if_not_exists()
+                    .col(uuid(RecoveryCode::Id).primary_key())
+                    .col(uuid(RecoveryCode::UserId))
+                    .col(string(RecoveryCode::CodeHash))
+                    .col(timestamp_with_time_zone_null(RecoveryCode::UsedAt))
+                    .col(
+                        timestamp_with_time_zone(RecoveryCode::CreatedAt)
+                            .default(Expr::current_timestamp()),
+                    )
+                    .foreign_key(
+                        ForeignKey::create()
+                            .name("fk-recovery_code-user_id")
+                            .from(RecoveryCode::Table, RecoveryCode::UserId)
+                            .to(User::Table, User::Id)
+                            .on_delete(ForeignKeyAction::Cascade),
+                    )
+                    .to_owned(),
+            )
+            .await?;
+
+        manager
+            .create_index(
+                Index::create()
+                    .name("idx-recovery_code-user_id-code_hash")
+                    .table(RecoveryCode::Table)
+                    .col(RecoveryCode::UserId)
+                    .col(RecoveryCode::CodeHash)
+                    .to_owned(),
+            )
+            .await
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .drop_table(Table::drop().table(RecoveryCode::Table).to_owned())
+            .await
+    }
+}
+
+#[derive(DeriveIden)]
+enum RecoveryCode {
+    Table,
+    Id,
+    UserId,
+    CodeHash,
+    UsedAt,
+    CreatedAt,
+}
+
+#[derive(DeriveIden)]
+enum User {
+    Table,
+    Id,
+}
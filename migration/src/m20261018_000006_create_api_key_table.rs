@@ -0,0 +1,73 @@
+use sea_orm_migration::{prelude::*, schema::*};
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .create_table(
+                Table::create()
+                    .table(ApiKey::Table)
+                    .if_not_exists()
+                    .col(uuid(ApiKey::Id).primary_key())
+                    .col(uuid(ApiKey::UserId))
+                    .col(string(ApiKey::Name))
+                    .col(string(ApiKey::Prefix))
+                    .col(string_uniq(ApiKey::KeyHash))
+                    .col(string(ApiKey::Scopes))
+                    .col(timestamp_with_time_zone_null(ApiKey::ExpiresAt))
+                    .col(timestamp_with_time_zone_null(ApiKey::LastUsedAt))
+                    .col(
+                        timestamp_with_time_zone(ApiKey::CreatedAt)
+                            .default(Expr::current_timestamp()),
+                    )
+                    .foreign_key(
+                        ForeignKey::create()
+                            .name("fk-api_key-user_id")
+                            .from(ApiKey::Table, ApiKey::UserId)
+                            .to(User::Table, User::Id)
+                            .on_delete(ForeignKeyAction::Cascade),
+                    )
+                    .to_owned(),
+            )
+            .await?;
+
+        manager
+            .create_index(
+                Index::create()
+                    .name("idx-api_key-user_id")
+                    .table(ApiKey::Table)
+                    .col(ApiKey::UserId)
+                    .to_owned(),
+            )
+            .await
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .drop_table(Table::drop().table(ApiKey::Table).to_owned())
+            .await
+    }
+}
+
+#[derive(DeriveIden)]
+enum ApiKey {
+    Table,
+    Id,
+    UserId,
+    Name,
+    Prefix,
+    KeyHash,
+    Scopes,
+    ExpiresAt,
+    LastUsedAt,
+    CreatedAt,
+}
+
+#[derive(DeriveIden)]
+enum User {
+    Table,
+    Id,
+}
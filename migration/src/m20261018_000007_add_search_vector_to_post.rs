@@ -0,0 +1,40 @@
+use sea_orm_migration::prelude::*;
+use sea_orm_migration::sea_orm::DbBackend;
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+// tsvector only exists on PostgreSQL, other databases search with LIKE
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        if manager.get_database_backend() != DbBackend::Postgres {
+            return Ok(());
+        }
+
+        let db = manager.get_connection();
+        db.execute_unprepared(
+            "ALTER TABLE post ADD COLUMN search_vector tsvector GENERATED ALWAYS AS (
+                setweight(to_tsvector('simple', coalesce(title, '')), 'A') ||
+                setweight(to_tsvector('simple', coalesce(text, '')), 'B')
+            ) STORED",
+        )
+        .await?;
+        db.execute_unprepared("CREATE INDEX idx_post_search_vector ON post USING GIN (search_vector)")
+            .await?;
+
+        Ok(())
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        if manager.get_database_backend() != DbBackend::Postgres {
+            return Ok(());
+        }
+
+        let db = manager.get_connection();
+        db.execute_unprepared("DROP INDEX IF EXISTS idx_post_search_vector").await?;
+        db.execute_unprepared("ALTER TABLE post DROP COLUMN IF EXISTS search_vector").await?;
+
+        Ok(())
+    }
+}
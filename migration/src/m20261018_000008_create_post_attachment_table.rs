@@ -0,0 +1,84 @@
+use sea_orm_migration::{prelude::*, schema::*};
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .create_table(
+                Table::create()
+                    .table(PostAttachment::Table)
+                    .if_not_exists()
+                    .col(uuid(PostAttachment::Id).primary_key())
+                    .col(uuid(PostAttachment::PostId))
+                    .col(uuid(PostAttachment::UserId))
+                    .col(string(PostAttachment::FileName))
+                    .col(string(PostAttachment::FilePath))
+                    .col(big_integer(PostAttachment::Size))
+                    .col(string(PostAttachment::Hash))
+                    .col(
+                        timestamp_with_time_zone(PostAttachment::CreatedAt)
+                            .default(Expr::current_timestamp()),
+                    )
+                    .foreign_key(
+                        ForeignKey::create()
+                            .name("fk-post_attachment-post_id")
+                            .from(PostAttachment::Table, PostAttachment::PostId)
+                            .to(Post::Table, Post::Id)
+                            .on_delete(ForeignKeyAction::Cascade),
+                    )
+                    .foreign_key(
+                        ForeignKey::create()
+                            .name("fk-post_attachment-user_id")
+                            .from(PostAttachment::Table, PostAttachment::UserId)
+                            .to(User::Table, User::Id)
+                            .on_delete(ForeignKeyAction::Cascade),
+                    )
+                    .to_owned(),
+            )
+            .await?;
+
+        manager
+            .create_index(
+                Index::create()
+                    .name("idx-post_attachment-post_id")
+                    .table(PostAttachment::Table)
+                    .col(PostAttachment::PostId)
+                    .to_owned(),
+            )
+            .await
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .drop_table(Table::drop().table(PostAttachment::Table).to_owned())
+            .await
+    }
+}
+
+#[derive(DeriveIden)]
+enum PostAttachment {
+    Table,
+    Id,
+    PostId,
+    UserId,
+    FileName,
+    FilePath,
+    Size,
+    Hash,
+    CreatedAt,
+}
+
+#[derive(DeriveIden)]
+enum Post {
+    Table,
+    Id,
+}
+
+#[derive(DeriveIden)]
+enum User {
+    Table,
+    Id,
+}
@@ -0,0 +1,145 @@
+use crate::app::auth::extractor::AuthUser;
+use crate::app::auth::permission::Role;
+use crate::app::hashing::hash::{generate_token, hash_token};
+use crate::error::AppError;
+use axum::http::Method;
+use chrono::{DateTime, Duration, Utc};
+use entity::{api_key, user};
+use sea_orm::{
+    ActiveModelTrait, ColumnTrait, DatabaseConnection, EntityTrait, IntoActiveModel, QueryFilter,
+    Set,
+};
+use serde::{Deserialize, Serialize};
+use tracing::warn;
+use uuid::Uuid;
+
+/// Marks our keys so they are easy to spot in logs and secret scanners
+const KEY_PREFIX: &str = "lmr_";
+/// Characters of the random part kept in clear to tell keys apart
+const VISIBLE_CHARS: usize = 8;
+/// `last_used_at` is written at most this often per key
+const TOUCH_INTERVAL_SECS: i64 = 60;
+
+/// What a key may do on top of the owner's role
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Scope {
+    /// `GET` and `HEAD` requests
+    Read,
+    /// Every other method
+    Write,
+    /// The permissions of the owner's role, see `Permission`
+    Admin,
+}
+
+impl Scope {
+    pub fn parse(value: &str) -> Option<Self> {
+        match value {
+            "read" => Some(Self::Read),
+            "write" => Some(Self::Write),
+            "admin" => Some(Self::Admin),
+            _ => None,
+        }
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Read => "read",
+            Self::Write => "write",
+            Self::Admin => "admin",
+        }
+    }
+
+    /// The scope a request with this method needs
+    pub fn for_method(method: &Method) -> Self {
+        if method == Method::GET || method == Method::HEAD {
+            Self::Read
+        } else {
+            Self::Write
+        }
+    }
+}
+
+/// Scopes are stored comma separated in `api_key.scopes`
+pub fn parse_scopes(value: &str) -> Vec<Scope> {
+    value.split(',').filter_map(|scope| Scope::parse(scope.trim())).collect()
+}
+
+fn join_scopes(scopes: &[Scope]) -> String {
+    scopes.iter().map(Scope::as_str).collect::<Vec<_>>().join(",")
+}
+
+/// Create a key for `user_id`. The plain key is returned once and only its hash is stored.
+pub async fn create_api_key(
+    db: &DatabaseConnection,
+    user_id: Uuid,
+    name: &str,
+    scopes: &[Scope],
+    expires_at: Option<DateTime<Utc>>,
+) -> Result<(api_key::Model, String), AppError> {
+    if scopes.is_empty() {
+        return Err(AppError::bad_request("An API key needs at least one scope"));
+    }
+
+    let secret = generate_token(32)?;
+    let key = format!("{}{}", KEY_PREFIX, secret);
+
+    let model = api_key::ActiveModel {
+        id: Set(Uuid::new_v4()),
+        user_id: Set(user_id),
+        name: Set(name.to_string()),
+        prefix: Set(key[..KEY_PREFIX.len() + VISIBLE_CHARS].to_string()),
+        key_hash: Set(hash_token(&key)),
+        scopes: Set(join_scopes(scopes)),
+        expires_at: Set(expires_at),
+        last_used_at: Set(None),
+        created_at: Set(Utc::now()),
+    }
+    .insert(db)
+    .await?;
+
+    Ok((model, key))
+}
+
+/// Resolve an `Authorization: ApiKey <key>` header to its owner
+pub async fn authenticate_api_key(db: &DatabaseConnection, key: &str) -> Result<AuthUser, AppError> {
+    let (key_model, owner) = api_key::Entity::find()
+        .filter(api_key::Column::KeyHash.eq(hash_token(key)))
+        .find_also_related(user::Entity)
+        .one(db)
+        .await?
+        .ok_or_else(|| AppError::unauthorized("Invalid API key"))?;
+    let owner = owner.ok_or_else(|| AppError::unauthorized("Invalid API key"))?;
+
+    let now = Utc::now();
+    if key_model.expires_at.is_some_and(|expires_at| expires_at <= now) {
+        return Err(AppError::unauthorized("API key expired"));
+    }
+
+    let scopes = parse_scopes(&key_model.scopes);
+    touch_api_key(db, key_model, now).await;
+
+    Ok(AuthUser {
+        id: owner.id,
+        username: owner.username,
+        role: Role::parse(&owner.role),
+        scopes: Some(scopes),
+    })
+}
+
+/// Record the use of a key. Failing to do so must not fail the request.
+async fn touch_api_key(db: &DatabaseConnection, key_model: api_key::Model, now: DateTime<Utc>) {
+    let recently_used = key_model
+        .last_used_at
+        .is_some_and(|last| now - last < Duration::seconds(TOUCH_INTERVAL_SECS));
+    if recently_used {
+        return;
+    }
+
+    let id = key_model.id;
+    let mut key = key_model.into_active_model();
+    key.last_used_at = Set(Some(now));
+    if let Err(err) = key.update(db).await {
+        warn!("Could not update last use of API key {}: {}", id, err);
+    }
+}
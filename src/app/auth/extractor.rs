@@ -0,0 +1,108 @@
+use crate::app::auth::api_key::{authenticate_api_key, Scope};
+use crate::app::auth::jwt::{decode_token, TokenKind};
+use crate::app::auth::permission::{Permission, Role};
+use crate::error::AppError;
+use crate::utils::AppState;
+use axum::extract::{FromRequestParts, OptionalFromRequestParts};
+use axum::http::header::AUTHORIZATION;
+use axum::http::request::Parts;
+use entity::user;
+use sea_orm::EntityTrait;
+use std::sync::Arc;
+use uuid::Uuid;
+
+/// The caller identified by an `Authorization: Bearer <token>` or
+/// `Authorization: ApiKey <key>` header
+#[derive(Debug, Clone)]
+pub struct AuthUser {
+    pub id: Uuid,
+    pub username: String,
+    pub role: Role,
+    /// Scopes of the API key used, `None` for a logged in session
+    pub scopes: Option<Vec<Scope>>,
+}
+
+impl AuthUser {
+    pub fn has(&self, permission: Permission) -> bool {
+        self.role.permissions().contains(&permission) && self.has_scope(Scope::Admin)
+    }
+
+    /// Sessions have every scope
+    pub fn has_scope(&self, scope: Scope) -> bool {
+        self.scopes.as_ref().is_none_or(|scopes| scopes.contains(&scope))
+    }
+
+    pub fn is_api_key(&self) -> bool {
+        self.scopes.is_some()
+    }
+
+    /// Account security is only managed from a logged in session, a leaked
+    /// API key must not be able to lock the owner out. `action` completes
+    /// "API keys can't ...".
+    pub fn require_session(&self, action: &str) -> Result<(), AppError> {
+        if self.is_api_key() {
+            return Err(AppError::forbidden(format!("API keys can't {}", action)));
+        }
+        Ok(())
+    }
+}
+
+impl FromRequestParts<Arc<AppState>> for AuthUser {
+    type Rejection = AppError;
+
+    async fn from_request_parts(parts: &mut Parts, state: &Arc<AppState>) -> Result<Self, Self::Rejection> {
+        let header = parts
+            .headers
+            .get(AUTHORIZATION)
+            .and_then(|value| value.to_str().ok())
+            .ok_or_else(|| AppError::unauthorized("Missing authorization header"))?;
+
+        if let Some(key) = header.strip_prefix("ApiKey ") {
+            let auth = authenticate_api_key(&state.database_connection, key.trim()).await?;
+
+            let needed = Scope::for_method(&parts.method);
+            if !auth.has_scope(needed) {
+                return Err(AppError::forbidden(format!(
+                    "API key is missing the {} scope",
+                    needed.as_str()
+                )));
+            }
+            return Ok(auth);
+        }
+
+        let token = header
+            .strip_prefix("Bearer ")
+            .ok_or_else(|| AppError::unauthorized("Unsupported authorization scheme"))?;
+
+        let claims = decode_token(token.trim(), TokenKind::Access)
+            .map_err(|_| AppError::unauthorized("Invalid or expired token"))?;
+
+        // The role in the token is from login time, a demotion has to apply now
+        let user = user::Entity::find_by_id(claims.sub)
+            .one(&state.database_connection)
+            .await?
+            .ok_or_else(|| AppError::unauthorized("Invalid or expired token"))?;
+
+        Ok(AuthUser {
+            id: user.id,
+            username: user.username,
+            role: Role::parse(&user.role),
+            scopes: None,
+        })
+    }
+}
+
+/// `Option<AuthUser>` for routes anyone may call: `None` without an
+/// `Authorization` header, a bad one is still rejected
+impl OptionalFromRequestParts<Arc<AppState>> for AuthUser {
+    type Rejection = AppError;
+
+    async fn from_request_parts(parts: &mut Parts, state: &Arc<AppState>) -> Result<Option<Self>, Self::Rejection> {
+        if !parts.headers.contains_key(AUTHORIZATION) {
+            return Ok(None);
+        }
+        <Self as FromRequestParts<Arc<AppState>>>::from_request_parts(parts, state)
+            .await
+            .map(Some)
+    }
+}
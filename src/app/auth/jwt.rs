@@ -0,0 +1,130 @@
+use crate::app::auth::permission::Role;
+use anyhow::Context;
+use chrono::{DateTime, Duration, Utc};
+use entity::user;
+use jsonwebtoken::{decode, encode, DecodingKey, EncodingKey, Header, Validation};
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum TokenKind {
+    Access,
+    Refresh,
+    /// Proves the password step passed while the second factor is pending
+    Mfa,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Claims {
+    pub sub: Uuid,
+    pub username: String,
+    pub role: Role,
+    pub kind: TokenKind,
+    pub jti: Uuid,
+    pub iat: i64,
+    pub exp: i64,
+}
+
+#[derive(Debug, Serialize)]
+pub struct TokenPair {
+    pub access_token: String,
+    pub refresh_token: String,
+    pub token_type: &'static str,
+    pub expires_in: i64,
+    /// `jti` of the refresh token, used as its row id in `refresh_token`
+    #[serde(skip)]
+    pub refresh_id: Uuid,
+    #[serde(skip)]
+    pub refresh_expires_at: DateTime<Utc>,
+}
+
+struct JwtConfig {
+    secret: String,
+    access_ttl: i64,
+    refresh_ttl: i64,
+    mfa_ttl: i64,
+}
+
+impl JwtConfig {
+    fn from_env() -> anyhow::Result<Self> {
+        let secret = std::env::var("JWT_SECRET").context("JWT_SECRET is not set")?;
+        let access_ttl = std::env::var("JWT_ACCESS_TTL")
+            .unwrap_or_else(|_| "900".into())
+            .parse()?;
+        let refresh_ttl = std::env::var("JWT_REFRESH_TTL")
+            .unwrap_or_else(|_| "1209600".into())
+            .parse()?;
+        let mfa_ttl = std::env::var("JWT_MFA_TTL")
+            .unwrap_or_else(|_| "300".into())
+            .parse()?;
+
+        Ok(Self { secret, access_ttl, refresh_ttl, mfa_ttl })
+    }
+
+    fn ttl(&self, kind: TokenKind) -> i64 {
+        match kind {
+            TokenKind::Access => self.access_ttl,
+            TokenKind::Refresh => self.refresh_ttl,
+            TokenKind::Mfa => self.mfa_ttl,
+        }
+    }
+}
+
+fn sign(config: &JwtConfig, user: &user::Model, kind: TokenKind) -> anyhow::Result<(String, Claims)> {
+    let now = Utc::now();
+    let claims = Claims {
+        sub: user.id,
+        username: user.username.clone(),
+        role: Role::parse(&user.role),
+        kind,
+        jti: Uuid::new_v4(),
+        iat: now.timestamp(),
+        exp: (now + Duration::seconds(config.ttl(kind))).timestamp(),
+    };
+
+    let token = encode(
+        &Header::default(),
+        &claims,
+        &EncodingKey::from_secret(config.secret.as_bytes()),
+    )?;
+    Ok((token, claims))
+}
+
+/// Issue a fresh access token and refresh token for the given user
+pub fn issue_token_pair(user: &user::Model) -> anyhow::Result<TokenPair> {
+    let config = JwtConfig::from_env()?;
+    let (access_token, _) = sign(&config, user, TokenKind::Access)?;
+    let (refresh_token, refresh_claims) = sign(&config, user, TokenKind::Refresh)?;
+
+    Ok(TokenPair {
+        access_token,
+        refresh_token,
+        token_type: "Bearer",
+        expires_in: config.access_ttl,
+        refresh_id: refresh_claims.jti,
+        refresh_expires_at: DateTime::from_timestamp(refresh_claims.exp, 0).unwrap_or_else(Utc::now),
+    })
+}
+
+/// Issue the short-lived token exchanged for a session once the second factor is verified
+pub fn issue_mfa_token(user: &user::Model) -> anyhow::Result<(String, i64)> {
+    let config = JwtConfig::from_env()?;
+    let (token, _) = sign(&config, user, TokenKind::Mfa)?;
+    Ok((token, config.mfa_ttl))
+}
+
+/// Decode a token and make sure it is of the expected kind
+pub fn decode_token(token: &str, kind: TokenKind) -> anyhow::Result<Claims> {
+    let config = JwtConfig::from_env()?;
+    let data = decode::<Claims>(
+        token,
+        &DecodingKey::from_secret(config.secret.as_bytes()),
+        &Validation::default(),
+    )?;
+
+    if data.claims.kind != kind {
+        anyhow::bail!("Unexpected token kind");
+    }
+    Ok(data.claims)
+}
@@ -0,0 +1,10 @@
+pub mod api_key;
+pub mod extractor;
+pub mod jwt;
+pub mod password_reset;
+pub mod permission;
+pub mod policy;
+pub mod session;
+pub mod throttle;
+pub mod totp;
+pub mod two_factor;
@@ -0,0 +1,95 @@
+use crate::app::auth::session::revoke_user_sessions;
+use crate::app::hashing::hash::{generate_token, hash_password, hash_token};
+use crate::app::notify::notifier::Notifier;
+use crate::error::AppError;
+use chrono::{Duration, Utc};
+use entity::{password_reset_token, user};
+use sea_orm::sea_query::Expr;
+use sea_orm::{
+    ActiveModelTrait, ColumnTrait, DatabaseConnection, EntityTrait, IntoActiveModel, QueryFilter,
+    Set, TransactionTrait,
+};
+use uuid::Uuid;
+
+fn reset_ttl() -> i64 {
+    std::env::var("PASSWORD_RESET_TTL")
+        .ok()
+        .and_then(|v| v.parse().ok())
+        .unwrap_or(3600)
+}
+
+/// Create a one-time reset token for `user` and hand it to the notifier
+pub async fn request_password_reset(
+    db: &DatabaseConnection,
+    notifier: &dyn Notifier,
+    user: &user::Model,
+) -> Result<(), AppError> {
+    // Only the latest token stays valid
+    password_reset_token::Entity::delete_many()
+        .filter(password_reset_token::Column::UserId.eq(user.id))
+        .exec(db)
+        .await?;
+
+    let token = generate_token(32)?;
+    let now = Utc::now();
+
+    password_reset_token::ActiveModel {
+        id: Set(Uuid::new_v4()),
+        user_id: Set(user.id),
+        token_hash: Set(hash_token(&token)),
+        expires_at: Set(now + Duration::seconds(reset_ttl())),
+        created_at: Set(now),
+        ..Default::default()
+    }
+    .insert(db)
+    .await?;
+
+    notifier.send_password_reset(user, &token).await?;
+    Ok(())
+}
+
+/// Redeem a reset token: set the new password and end every session
+pub async fn redeem_password_reset(
+    db: &DatabaseConnection,
+    token: &str,
+    new_password: &str,
+) -> Result<(), AppError> {
+    let invalid = || AppError::bad_request("Invalid or expired reset token");
+
+    let stored = password_reset_token::Entity::find()
+        .filter(password_reset_token::Column::TokenHash.eq(hash_token(token)))
+        .filter(password_reset_token::Column::UsedAt.is_null())
+        .filter(password_reset_token::Column::ExpiresAt.gt(Utc::now()))
+        .one(db)
+        .await?
+        .ok_or_else(invalid)?;
+
+    let txn = db.begin().await?;
+
+    let consumed = password_reset_token::Entity::update_many()
+        .col_expr(password_reset_token::Column::UsedAt, Expr::value(Utc::now()))
+        .filter(password_reset_token::Column::Id.eq(stored.id))
+        .filter(password_reset_token::Column::UsedAt.is_null())
+        .exec(&txn)
+        .await?;
+
+    if consumed.rows_affected != 1 {
+        txn.rollback().await.ok();
+        return Err(invalid());
+    }
+
+    let user_model = user::Entity::find_by_id(stored.user_id)
+        .one(&txn)
+        .await?
+        .ok_or_else(invalid)?;
+
+    let mut user = user_model.into_active_model();
+    user.password = Set(hash_password(new_password)?);
+    user.updated_at = Set(Utc::now());
+    user.update(&txn).await?;
+
+    revoke_user_sessions(&txn, stored.user_id).await?;
+    txn.commit().await?;
+
+    Ok(())
+}
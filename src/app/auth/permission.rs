@@ -0,0 +1,99 @@
+use crate::app::auth::extractor::AuthUser;
+use crate::error::AppError;
+use axum::extract::Request;
+use axum::middleware::Next;
+use axum::response::{IntoResponse, Response};
+use entity::user;
+use futures::future::BoxFuture;
+use sea_orm::sea_query::Expr;
+use sea_orm::{ColumnTrait, DatabaseConnection, DbErr, EntityTrait, QueryFilter};
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Role {
+    Admin,
+    Editor,
+    Member,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Permission {
+    /// Create, rename and delete categories
+    ManageCategories,
+    /// See every registered user
+    ListUsers,
+    /// Change the role of other users
+    ManageRoles,
+    /// Edit or delete content owned by someone else
+    ModerateContent,
+}
+
+impl Role {
+    /// Parse the `user.role` column, unknown values get the least privilege
+    pub fn parse(value: &str) -> Self {
+        match value {
+            "admin" => Self::Admin,
+            "editor" => Self::Editor,
+            _ => Self::Member,
+        }
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Admin => "admin",
+            Self::Editor => "editor",
+            Self::Member => "member",
+        }
+    }
+
+    pub fn permissions(&self) -> &'static [Permission] {
+        match self {
+            Self::Admin => &[
+                Permission::ManageCategories,
+                Permission::ListUsers,
+                Permission::ManageRoles,
+                Permission::ModerateContent,
+            ],
+            Self::Editor => &[Permission::ManageCategories, Permission::ModerateContent],
+            Self::Member => &[],
+        }
+    }
+}
+
+/// Route-layer guard, use with `axum::middleware::from_fn_with_state`
+pub fn require(
+    permission: Permission,
+) -> impl Fn(AuthUser, Request, Next) -> BoxFuture<'static, Response> + Clone + Send + Sync + 'static
+{
+    move |auth, request, next| {
+        Box::pin(async move {
+            if !auth.has(permission) {
+                return AppError::forbidden("You don't have permission to do this").into_response();
+            }
+            next.run(request).await
+        })
+    }
+}
+
+/// Promote the usernames listed in `ADMIN_USERNAMES` (comma separated) to admin
+pub async fn promote_configured_admins(db: &DatabaseConnection) -> Result<(), DbErr> {
+    let usernames = std::env::var("ADMIN_USERNAMES")
+        .unwrap_or_default()
+        .split(',')
+        .map(|name| name.trim().to_string())
+        .filter(|name| !name.is_empty())
+        .collect::<Vec<_>>();
+
+    if usernames.is_empty() {
+        return Ok(());
+    }
+
+    user::Entity::update_many()
+        .col_expr(user::Column::Role, Expr::value(Role::Admin.as_str()))
+        .filter(user::Column::Username.is_in(usernames))
+        .exec(db)
+        .await?;
+    Ok(())
+}
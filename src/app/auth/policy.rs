@@ -0,0 +1,13 @@
+use crate::app::auth::extractor::AuthUser;
+use crate::app::auth::permission::Permission;
+use crate::error::AppError;
+use uuid::Uuid;
+
+/// Let the owner of a resource, or a moderator, through
+pub fn ensure_owner(auth: &AuthUser, owner_id: Option<Uuid>) -> Result<(), AppError> {
+    if owner_id == Some(auth.id) || auth.has(Permission::ModerateContent) {
+        Ok(())
+    } else {
+        Err(AppError::forbidden("You are not allowed to modify this resource"))
+    }
+}
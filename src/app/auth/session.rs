@@ -0,0 +1,146 @@
+use crate::app::auth::jwt::{decode_token, issue_token_pair, TokenKind, TokenPair};
+use crate::app::hashing::hash::hash_token;
+use crate::error::AppError;
+use chrono::Utc;
+use entity::{refresh_token, user};
+use sea_orm::sea_query::Expr;
+use sea_orm::{
+    ActiveModelTrait, ColumnTrait, ConnectionTrait, DatabaseConnection, EntityTrait, QueryFilter,
+    Set, TransactionTrait,
+};
+use tracing::warn;
+use uuid::Uuid;
+
+/// Issue tokens for `user` and remember the refresh token under `family_id`
+async fn issue_in_family<C>(db: &C, user: &user::Model, family_id: Uuid) -> Result<TokenPair, AppError>
+where
+    C: ConnectionTrait,
+{
+    let tokens = issue_token_pair(user)?;
+
+    refresh_token::ActiveModel {
+        id: Set(tokens.refresh_id),
+        user_id: Set(user.id),
+        family_id: Set(family_id),
+        token_hash: Set(hash_token(&tokens.refresh_token)),
+        expires_at: Set(tokens.refresh_expires_at),
+        created_at: Set(Utc::now()),
+        ..Default::default()
+    }
+    .insert(db)
+    .await?;
+
+    Ok(tokens)
+}
+
+/// Revoke every refresh token of a family that is still active
+async fn revoke_family<C>(db: &C, family_id: Uuid) -> Result<(), AppError>
+where
+    C: ConnectionTrait,
+{
+    refresh_token::Entity::update_many()
+        .col_expr(refresh_token::Column::RevokedAt, Expr::value(Utc::now()))
+        .filter(refresh_token::Column::FamilyId.eq(family_id))
+        .filter(refresh_token::Column::RevokedAt.is_null())
+        .exec(db)
+        .await?;
+    Ok(())
+}
+
+/// Look up the stored row of a refresh token after checking its signature
+async fn find_refresh_token(
+    db: &DatabaseConnection,
+    token: &str,
+) -> Result<refresh_token::Model, AppError> {
+    decode_token(token, TokenKind::Refresh)
+        .map_err(|_| AppError::unauthorized("Invalid or expired refresh token"))?;
+
+    refresh_token::Entity::find()
+        .filter(refresh_token::Column::TokenHash.eq(hash_token(token)))
+        .one(db)
+        .await?
+        .ok_or_else(|| AppError::unauthorized("Invalid or expired refresh token"))
+}
+
+/// Start a new login session (a new refresh token family)
+pub async fn start_session(
+    db: &DatabaseConnection,
+    user: &user::Model,
+) -> Result<TokenPair, AppError> {
+    // Drop this user's stale tokens so the table doesn't grow forever
+    refresh_token::Entity::delete_many()
+        .filter(refresh_token::Column::UserId.eq(user.id))
+        .filter(refresh_token::Column::ExpiresAt.lt(Utc::now()))
+        .exec(db)
+        .await?;
+
+    issue_in_family(db, user, Uuid::new_v4()).await
+}
+
+/// Exchange a refresh token for a new pair, revoking the family on reuse
+pub async fn rotate_session(
+    db: &DatabaseConnection,
+    token: &str,
+) -> Result<TokenPair, AppError> {
+    let stored = find_refresh_token(db, token).await?;
+
+    if stored.revoked_at.is_some() || stored.used_at.is_some() {
+        warn!("Refresh token reuse detected for family {}", stored.family_id);
+        revoke_family(db, stored.family_id).await?;
+        return Err(AppError::unauthorized("Refresh token has already been used"));
+    }
+
+    let txn = db.begin().await?;
+
+    // Only one concurrent request may consume the token
+    let consumed = refresh_token::Entity::update_many()
+        .col_expr(refresh_token::Column::UsedAt, Expr::value(Utc::now()))
+        .filter(refresh_token::Column::Id.eq(stored.id))
+        .filter(refresh_token::Column::UsedAt.is_null())
+        .exec(&txn)
+        .await?;
+
+    if consumed.rows_affected != 1 {
+        txn.rollback().await.ok();
+        revoke_family(db, stored.family_id).await?;
+        return Err(AppError::unauthorized("Refresh token has already been used"));
+    }
+
+    let user = user::Entity::find_by_id(stored.user_id)
+        .one(&txn)
+        .await?
+        .ok_or_else(|| AppError::unauthorized("User no longer exists"))?;
+
+    let tokens = issue_in_family(&txn, &user, stored.family_id).await?;
+    txn.commit().await?;
+
+    Ok(tokens)
+}
+
+/// End the session a refresh token belongs to, or every session of its user
+pub async fn end_session(
+    db: &DatabaseConnection,
+    token: &str,
+    all_sessions: bool,
+) -> Result<(), AppError> {
+    let stored = find_refresh_token(db, token).await?;
+
+    if !all_sessions {
+        return revoke_family(db, stored.family_id).await;
+    }
+    revoke_user_sessions(db, stored.user_id).await
+}
+
+/// Revoke every active refresh token of a user, e.g. after a password change
+pub async fn revoke_user_sessions<C>(db: &C, user_id: Uuid) -> Result<(), AppError>
+where
+    C: ConnectionTrait,
+{
+    refresh_token::Entity::update_many()
+        .col_expr(refresh_token::Column::RevokedAt, Expr::value(Utc::now()))
+        .filter(refresh_token::Column::UserId.eq(user_id))
+        .filter(refresh_token::Column::RevokedAt.is_null())
+        .exec(db)
+        .await?;
+    Ok(())
+}
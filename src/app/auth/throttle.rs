@@ -0,0 +1,381 @@
+use crate::error::AppError;
+use axum::http::HeaderMap;
+use chrono::{DateTime, Duration, Utc};
+use futures::future::BoxFuture;
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex, MutexGuard};
+
+/// Failed login attempts recorded for one key (a username or a client IP)
+#[derive(Debug, Clone, Default)]
+pub struct Attempts {
+    pub failures: u32,
+    pub last_failure: Option<DateTime<Utc>>,
+}
+
+/// Where failed attempts are kept, swap the in-memory store for a persistent one
+/// when the service runs on more than one instance
+pub trait AttemptStore: Send + Sync {
+    /// In one step: the longest wait any of `keys` is under, or, when none is
+    /// waiting, a failure recorded against every key. Parallel attempts can't
+    /// all pass the check before the first of them is counted.
+    fn begin_attempt<'a>(
+        &'a self,
+        keys: &'a [String],
+        at: DateTime<Utc>,
+        policy: &'a ThrottlePolicy,
+    ) -> BoxFuture<'a, anyhow::Result<Option<Duration>>>;
+    /// Take back one failure `begin_attempt` recorded
+    fn forgive<'a>(&'a self, key: &'a str) -> BoxFuture<'a, anyhow::Result<()>>;
+    fn clear<'a>(&'a self, key: &'a str) -> BoxFuture<'a, anyhow::Result<()>>;
+}
+
+/// Default store, forgets everything on restart
+pub struct MemoryAttemptStore {
+    entries: Mutex<HashMap<String, Attempts>>,
+    /// Entries idle for longer than this are dropped
+    retention: Duration,
+}
+
+impl MemoryAttemptStore {
+    pub fn new(retention: Duration) -> Self {
+        Self {
+            entries: Mutex::new(HashMap::new()),
+            retention,
+        }
+    }
+
+    fn lock(&self) -> anyhow::Result<MutexGuard<'_, HashMap<String, Attempts>>> {
+        self.entries.lock().map_err(|_| anyhow::anyhow!("Attempt store poisoned"))
+    }
+}
+
+impl AttemptStore for MemoryAttemptStore {
+    fn begin_attempt<'a>(
+        &'a self,
+        keys: &'a [String],
+        at: DateTime<Utc>,
+        policy: &'a ThrottlePolicy,
+    ) -> BoxFuture<'a, anyhow::Result<Option<Duration>>> {
+        Box::pin(async move {
+            let mut entries = self.lock()?;
+            let retention = self.retention;
+            entries.retain(|_, attempts| attempts.last_failure.is_some_and(|last| at - last < retention));
+
+            let wait = keys
+                .iter()
+                .filter_map(|key| entries.get(key))
+                .filter_map(|attempts| policy.retry_after(attempts, at))
+                .max();
+            if wait.is_some() {
+                return Ok(wait);
+            }
+
+            for key in keys {
+                let attempts = entries.entry(key.clone()).or_default();
+                attempts.failures += 1;
+                attempts.last_failure = Some(at);
+            }
+            Ok(None)
+        })
+    }
+
+    fn forgive<'a>(&'a self, key: &'a str) -> BoxFuture<'a, anyhow::Result<()>> {
+        Box::pin(async move {
+            let mut entries = self.lock()?;
+            if let Some(attempts) = entries.get_mut(key) {
+                attempts.failures = attempts.failures.saturating_sub(1);
+            }
+            Ok(())
+        })
+    }
+
+    fn clear<'a>(&'a self, key: &'a str) -> BoxFuture<'a, anyhow::Result<()>> {
+        Box::pin(async move {
+            self.lock()?.remove(key);
+            Ok(())
+        })
+    }
+}
+
+/// How hard failed logins are slowed down
+#[derive(Debug, Clone)]
+pub struct ThrottlePolicy {
+    /// Failures allowed before any delay kicks in
+    pub free_attempts: u32,
+    /// Delay after the first failure past `free_attempts`, doubled for every further one
+    pub base_delay: Duration,
+    pub max_delay: Duration,
+    /// Failures after which the key is locked out for `lockout`
+    pub lockout_after: u32,
+    pub lockout: Duration,
+}
+
+impl ThrottlePolicy {
+    pub fn from_env() -> Self {
+        let var = |name: &str, default: i64| {
+            std::env::var(name)
+                .ok()
+                .and_then(|v| v.parse().ok())
+                .unwrap_or(default)
+        };
+
+        Self {
+            free_attempts: var("LOGIN_FREE_ATTEMPTS", 3) as u32,
+            base_delay: Duration::seconds(var("LOGIN_BASE_DELAY_SECS", 1)),
+            max_delay: Duration::seconds(var("LOGIN_MAX_DELAY_SECS", 60)),
+            lockout_after: var("LOGIN_LOCKOUT_AFTER", 10) as u32,
+            lockout: Duration::seconds(var("LOGIN_LOCKOUT_SECS", 900)),
+        }
+    }
+
+    /// How long the key still has to wait before the next attempt
+    pub fn retry_after(&self, attempts: &Attempts, now: DateTime<Utc>) -> Option<Duration> {
+        let last = attempts.last_failure?;
+
+        let wait = if attempts.failures >= self.lockout_after {
+            self.lockout
+        } else if attempts.failures > self.free_attempts {
+            let exponent = (attempts.failures - self.free_attempts - 1).min(30);
+            (self.base_delay * 2i32.pow(exponent)).min(self.max_delay)
+        } else {
+            return None;
+        };
+
+        let remaining = last + wait - now;
+        (remaining > Duration::zero()).then_some(remaining)
+    }
+}
+
+/// Tracks failed logins per username and per client IP
+pub struct LoginThrottle {
+    store: Arc<dyn AttemptStore>,
+    policy: ThrottlePolicy,
+}
+
+impl LoginThrottle {
+    pub fn new(store: Arc<dyn AttemptStore>, policy: ThrottlePolicy) -> Self {
+        Self { store, policy }
+    }
+
+    /// In-memory throttle configured from the `LOGIN_*` variables
+    pub fn from_env() -> Self {
+        let policy = ThrottlePolicy::from_env();
+        let store = MemoryAttemptStore::new(policy.lockout);
+        Self::new(Arc::new(store), policy)
+    }
+
+    fn keys(username: &str, ip: &str) -> [String; 2] {
+        [format!("user:{}", username.to_lowercase()), format!("ip:{}", ip)]
+    }
+
+    /// Start a login attempt, rejected with `429 Too Many Requests` while a key
+    /// is backing off. The attempt counts as failed until `record_success` or
+    /// `cancel` says otherwise, so a wrong password needs no further call.
+    pub async fn begin(&self, username: &str, ip: &str) -> Result<(), AppError> {
+        let keys = Self::keys(username, ip);
+        let wait = self.store.begin_attempt(&keys, Utc::now(), &self.policy).await?;
+
+        if let Some(wait) = wait {
+            // Round up so clients never retry a second too early
+            let seconds = (wait.num_milliseconds() + 999) / 1000;
+            return Err(AppError::TooManyRequests {
+                message: format!("Too many failed login attempts, retry in {} seconds", seconds),
+                retry_after: seconds,
+            });
+        }
+        Ok(())
+    }
+
+    /// A successful login clears the account, the IP keeps decaying on its own
+    pub async fn record_success(&self, username: &str, ip: &str) -> Result<(), AppError> {
+        let [user_key, ip_key] = Self::keys(username, ip);
+        self.store.clear(&user_key).await?;
+        self.store.forgive(&ip_key).await?;
+        Ok(())
+    }
+
+    /// The attempt was neither a success nor a failure, e.g. the password was
+    /// right and the second factor is still to come
+    pub async fn cancel(&self, username: &str, ip: &str) -> Result<(), AppError> {
+        for key in Self::keys(username, ip) {
+            self.store.forgive(&key).await?;
+        }
+        Ok(())
+    }
+}
+
+/// The caller's IP. With `TRUST_PROXY=true` it is read from `X-Forwarded-For`,
+/// `TRUSTED_PROXY_HOPS` entries from the right (default 1, the address the
+/// nearest proxy appended). Entries further left are whatever the client sent.
+pub fn client_ip(headers: &HeaderMap, peer: SocketAddr) -> String {
+    let trust_proxy = std::env::var("TRUST_PROXY").is_ok_and(|v| v == "true");
+
+    if trust_proxy {
+        let hops = std::env::var("TRUSTED_PROXY_HOPS")
+            .ok()
+            .and_then(|v| v.parse().ok())
+            .unwrap_or(1);
+        if let Some(ip) = forwarded_ip(headers, hops) {
+            return ip.to_string();
+        }
+    }
+    peer.ip().to_string()
+}
+
+/// Entry `hops` from the right of `X-Forwarded-For`, `None` when the header
+/// is shorter than that
+fn forwarded_ip(headers: &HeaderMap, hops: usize) -> Option<&str> {
+    let forwarded = headers.get("x-forwarded-for")?.to_str().ok()?;
+    let ip = forwarded.rsplit(',').nth(hops.checked_sub(1)?)?.trim();
+    (!ip.is_empty()).then_some(ip)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::response::IntoResponse;
+
+    fn policy() -> ThrottlePolicy {
+        ThrottlePolicy {
+            free_attempts: 3,
+            base_delay: Duration::seconds(1),
+            max_delay: Duration::seconds(60),
+            lockout_after: 10,
+            lockout: Duration::seconds(900),
+        }
+    }
+
+    fn attempts(failures: u32, last: DateTime<Utc>) -> Attempts {
+        Attempts {
+            failures,
+            last_failure: Some(last),
+        }
+    }
+
+    fn throttle() -> LoginThrottle {
+        let policy = policy();
+        LoginThrottle::new(Arc::new(MemoryAttemptStore::new(policy.lockout)), policy)
+    }
+
+    #[test]
+    fn free_attempts_have_no_delay() {
+        let now = Utc::now();
+        assert_eq!(policy().retry_after(&Attempts::default(), now), None);
+        assert_eq!(policy().retry_after(&attempts(3, now), now), None);
+    }
+
+    #[test]
+    fn backoff_doubles_up_to_the_cap() {
+        let now = Utc::now();
+        let policy = policy();
+
+        assert_eq!(policy.retry_after(&attempts(4, now), now), Some(Duration::seconds(1)));
+        assert_eq!(policy.retry_after(&attempts(5, now), now), Some(Duration::seconds(2)));
+        assert_eq!(policy.retry_after(&attempts(6, now), now), Some(Duration::seconds(4)));
+        assert_eq!(policy.retry_after(&attempts(9, now), now), Some(Duration::seconds(32)));
+
+        let capped = ThrottlePolicy { lockout_after: 100, ..policy };
+        assert_eq!(capped.retry_after(&attempts(20, now), now), Some(Duration::seconds(60)));
+    }
+
+    #[test]
+    fn backoff_counts_from_the_last_failure() {
+        let last = Utc::now();
+        let policy = policy();
+
+        assert_eq!(
+            policy.retry_after(&attempts(5, last), last + Duration::milliseconds(500)),
+            Some(Duration::milliseconds(1500))
+        );
+        assert_eq!(policy.retry_after(&attempts(5, last), last + Duration::seconds(2)), None);
+    }
+
+    #[test]
+    fn lockout_expires() {
+        let last = Utc::now();
+        let policy = policy();
+
+        assert_eq!(policy.retry_after(&attempts(10, last), last), Some(Duration::seconds(900)));
+        assert_eq!(
+            policy.retry_after(&attempts(10, last), last + Duration::seconds(899)),
+            Some(Duration::seconds(1))
+        );
+        assert_eq!(policy.retry_after(&attempts(10, last), last + Duration::seconds(900)), None);
+    }
+
+    #[tokio::test]
+    async fn blocks_once_free_attempts_are_used() {
+        let throttle = throttle();
+        for _ in 0..4 {
+            throttle.begin("alice", "10.0.0.1").await.unwrap();
+        }
+
+        match throttle.begin("alice", "10.0.0.1").await {
+            Err(AppError::TooManyRequests { retry_after, .. }) => assert_eq!(retry_after, 1),
+            other => panic!("expected 429, got {:?}", other.map(|_| ())),
+        }
+    }
+
+    #[tokio::test]
+    async fn parallel_attempts_are_all_counted() {
+        let throttle = Arc::new(throttle());
+        let tasks = (0..20).map(|_| {
+            let throttle = throttle.clone();
+            tokio::spawn(async move { throttle.begin("alice", "10.0.0.1").await.is_ok() })
+        });
+
+        let mut passed = 0;
+        for task in tasks {
+            passed += task.await.unwrap() as u32;
+        }
+        assert_eq!(passed, policy().free_attempts + 1);
+    }
+
+    #[tokio::test]
+    async fn blocked_username_is_blocked_from_any_ip() {
+        let throttle = throttle();
+        for i in 0..4 {
+            throttle.begin("alice", &format!("10.0.0.{}", i)).await.unwrap();
+        }
+        assert!(throttle.begin("alice", "10.0.0.99").await.is_err());
+        assert!(throttle.begin("bob", "10.0.0.99").await.is_ok());
+    }
+
+    #[tokio::test]
+    async fn success_clears_the_username() {
+        let throttle = throttle();
+        for _ in 0..3 {
+            throttle.begin("alice", "10.0.0.1").await.unwrap();
+        }
+        throttle.begin("alice", "10.0.0.1").await.unwrap();
+        throttle.record_success("alice", "10.0.0.1").await.unwrap();
+
+        // The IP is left with its 3 earlier failures, one more is free
+        throttle.begin("alice", "10.0.0.1").await.unwrap();
+    }
+
+    #[test]
+    fn too_many_requests_sets_retry_after() {
+        let response = AppError::TooManyRequests {
+            message: "slow down".to_string(),
+            retry_after: 2,
+        }
+        .into_response();
+
+        assert_eq!(response.status(), axum::http::StatusCode::TOO_MANY_REQUESTS);
+        assert_eq!(response.headers()["retry-after"], "2");
+    }
+
+    #[test]
+    fn forwarded_ip_is_counted_from_the_right() {
+        let mut headers = HeaderMap::new();
+        headers.insert("x-forwarded-for", "6.6.6.6, 203.0.113.7, 10.0.0.2".parse().unwrap());
+
+        assert_eq!(forwarded_ip(&headers, 1), Some("10.0.0.2"));
+        assert_eq!(forwarded_ip(&headers, 2), Some("203.0.113.7"));
+        assert_eq!(forwarded_ip(&headers, 4), None);
+        assert_eq!(forwarded_ip(&headers, 0), None);
+        assert_eq!(forwarded_ip(&HeaderMap::new(), 1), None);
+    }
+}
@@ -0,0 +1,156 @@
+use crate::app::hashing::hash::generate_token;
+use anyhow::Context;
+use hmac::{Hmac, Mac};
+use rand::rand_core::OsRng;
+use rand::TryRngCore;
+use sha1::Sha1;
+
+/// RFC 6238 defaults, the only settings authenticator apps reliably support
+const DIGITS: u32 = 6;
+const PERIOD: i64 = 30;
+/// Accept the previous and next step too, to absorb clock drift
+const SKEW: i64 = 1;
+const SECRET_LEN: usize = 20;
+pub const RECOVERY_CODES: usize = 10;
+
+/// A fresh random secret, base32 encoded as authenticator apps expect it
+pub fn generate_secret() -> anyhow::Result<String> {
+    let mut bytes = [0u8; SECRET_LEN];
+    OsRng.try_fill_bytes(&mut bytes)?;
+    Ok(base32::encode(base32::Alphabet::Rfc4648 { padding: false }, &bytes))
+}
+
+/// The `otpauth://` URI rendered as a QR code by the client
+pub fn otpauth_uri(secret: &str, username: &str) -> String {
+    let issuer = std::env::var("TOTP_ISSUER").unwrap_or_else(|_| "projek2".into());
+    format!(
+        "otpauth://totp/{issuer}:{account}?secret={secret}&issuer={issuer}&algorithm=SHA1&digits={DIGITS}&period={PERIOD}",
+        issuer = urlencoding::encode(&issuer),
+        account = urlencoding::encode(username),
+    )
+}
+
+/// RFC 4226 HOTP value with `digits` digits for one counter
+fn hotp(secret: &[u8], counter: u64, digits: u32) -> anyhow::Result<u32> {
+    let mut mac = Hmac::<Sha1>::new_from_slice(secret)?;
+    mac.update(&counter.to_be_bytes());
+    let digest = mac.finalize().into_bytes();
+
+    let offset = (digest[digest.len() - 1] & 0x0f) as usize;
+    let binary = u32::from_be_bytes([
+        digest[offset] & 0x7f,
+        digest[offset + 1],
+        digest[offset + 2],
+        digest[offset + 3],
+    ]);
+    Ok(binary % 10u32.pow(digits))
+}
+
+/// Check `code` around `unix_time` and return the matching time step.
+/// Steps at or below `last_step` were already used and are rejected.
+pub fn verify_code(secret: &str, code: &str, unix_time: i64, last_step: Option<i64>) -> anyhow::Result<Option<i64>> {
+    let key = base32::decode(base32::Alphabet::Rfc4648 { padding: false }, secret)
+        .context("Invalid TOTP secret")?;
+    let code = code.trim();
+    if code.len() != DIGITS as usize {
+        return Ok(None);
+    }
+    let Ok(code) = code.parse::<u32>() else {
+        return Ok(None);
+    };
+
+    let current = unix_time / PERIOD;
+    for step in (current - SKEW)..=(current + SKEW) {
+        if step < 0 || last_step.is_some_and(|last| step <= last) {
+            continue;
+        }
+        if hotp(&key, step as u64, DIGITS)? == code {
+            return Ok(Some(step));
+        }
+    }
+    Ok(None)
+}
+
+/// One-time recovery codes shown to the user once, e.g. `3f9a-0c41-77de-b2e8`
+pub fn generate_recovery_codes() -> anyhow::Result<Vec<String>> {
+    (0..RECOVERY_CODES)
+        .map(|_| {
+            let raw = generate_token(8)?;
+            Ok(raw
+                .as_bytes()
+                .chunks(4)
+                .map(|chunk| String::from_utf8_lossy(chunk).into_owned())
+                .collect::<Vec<_>>()
+                .join("-"))
+        })
+        .collect()
+}
+
+/// Recovery codes are compared without dashes, spaces or case
+pub fn normalize_recovery_code(code: &str) -> String {
+    code.chars()
+        .filter(|c| c.is_ascii_alphanumeric())
+        .collect::<String>()
+        .to_lowercase()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The SHA-1 seed of RFC 6238 Appendix B, "12345678901234567890"
+    const SEED: &[u8] = b"12345678901234567890";
+    const SEED_BASE32: &str = "GEZDGNBVGY3TQOJQGEZDGNBVGY3TQOJQ";
+
+    #[test]
+    fn rfc6238_sha1_vectors() {
+        let vectors = [
+            (59, 94287082),
+            (1111111109, 7081804),
+            (1111111111, 14050471),
+            (1234567890, 89005924),
+            (2000000000, 69279037),
+            (20000000000, 65353130),
+        ];
+        for (time, expected) in vectors {
+            assert_eq!(hotp(SEED, (time / PERIOD) as u64, 8).unwrap(), expected, "T = {}", time);
+        }
+    }
+
+    #[test]
+    fn accepts_the_current_code_and_returns_its_step() {
+        // Six digit code of the first vector: 94287082
+        assert_eq!(verify_code(SEED_BASE32, "287082", 59, None).unwrap(), Some(1));
+    }
+
+    #[test]
+    fn accepts_one_step_of_drift() {
+        assert_eq!(verify_code(SEED_BASE32, "287082", 59 + PERIOD, None).unwrap(), Some(1));
+        assert_eq!(verify_code(SEED_BASE32, "287082", 59 - PERIOD, None).unwrap(), Some(1));
+    }
+
+    #[test]
+    fn rejects_codes_outside_the_window() {
+        assert_eq!(verify_code(SEED_BASE32, "287082", 59 + 2 * PERIOD, None).unwrap(), None);
+        assert_eq!(verify_code(SEED_BASE32, "081804", 1111111109 + 2 * PERIOD, None).unwrap(), None);
+    }
+
+    #[test]
+    fn rejects_replayed_codes() {
+        assert_eq!(verify_code(SEED_BASE32, "287082", 59, Some(1)).unwrap(), None);
+        assert_eq!(verify_code(SEED_BASE32, "287082", 59, Some(2)).unwrap(), None);
+        assert_eq!(verify_code(SEED_BASE32, "287082", 59, Some(0)).unwrap(), Some(1));
+    }
+
+    #[test]
+    fn rejects_malformed_codes() {
+        assert_eq!(verify_code(SEED_BASE32, "28708", 59, None).unwrap(), None);
+        assert_eq!(verify_code(SEED_BASE32, "28708a", 59, None).unwrap(), None);
+        assert_eq!(verify_code(SEED_BASE32, "94287082", 59, None).unwrap(), None);
+    }
+
+    #[test]
+    fn recovery_codes_ignore_format() {
+        assert_eq!(normalize_recovery_code(" 3F9A-0c41 77de-b2e8 "), "3f9a0c4177deb2e8");
+    }
+}
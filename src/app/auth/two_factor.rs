@@ -0,0 +1,196 @@
+use crate::app::auth::totp::{
+    generate_recovery_codes, generate_secret, normalize_recovery_code, otpauth_uri, verify_code,
+};
+use crate::app::hashing::hash::hash_token;
+use crate::error::AppError;
+use chrono::Utc;
+use entity::{recovery_code, user};
+use sea_orm::sea_query::Expr;
+use sea_orm::{
+    ActiveModelTrait, ColumnTrait, ConnectionTrait, DatabaseConnection, EntityTrait,
+    IntoActiveModel, QueryFilter, Set, TransactionTrait,
+};
+use uuid::Uuid;
+
+/// The second factor presented by the client, one of the two is enough
+pub struct SecondFactor<'a> {
+    pub code: Option<&'a str>,
+    pub recovery_code: Option<&'a str>,
+}
+
+fn invalid_code() -> AppError {
+    AppError::unauthorized("Invalid two-factor code")
+}
+
+/// Check a TOTP code and remember its step so it can't be replayed
+async fn consume_totp<C>(db: &C, user_model: &user::Model, code: &str) -> Result<bool, AppError>
+where
+    C: ConnectionTrait,
+{
+    let Some(secret) = user_model.totp_secret.as_deref() else {
+        return Ok(false);
+    };
+
+    let step = verify_code(secret, code, Utc::now().timestamp(), user_model.totp_last_step)?;
+    let Some(step) = step else {
+        return Ok(false);
+    };
+
+    // Only one request can move the step forward, a parallel one with the
+    // same code matches no row and is rejected
+    let claimed = user::Entity::update_many()
+        .col_expr(user::Column::TotpLastStep, Expr::value(step))
+        .filter(user::Column::Id.eq(user_model.id))
+        .filter(
+            user::Column::TotpLastStep
+                .is_null()
+                .or(user::Column::TotpLastStep.lt(step)),
+        )
+        .exec(db)
+        .await?;
+    Ok(claimed.rows_affected == 1)
+}
+
+/// Mark a matching, unused recovery code as used. The `used_at IS NULL`
+/// filter makes a second redemption of the same code match no row.
+async fn consume_recovery_code<C>(db: &C, user_id: Uuid, code: &str) -> Result<bool, AppError>
+where
+    C: ConnectionTrait,
+{
+    let consumed = recovery_code::Entity::update_many()
+        .col_expr(recovery_code::Column::UsedAt, Expr::value(Utc::now()))
+        .filter(recovery_code::Column::UserId.eq(user_id))
+        .filter(recovery_code::Column::CodeHash.eq(hash_token(&normalize_recovery_code(code))))
+        .filter(recovery_code::Column::UsedAt.is_null())
+        .exec(db)
+        .await?;
+    Ok(consumed.rows_affected == 1)
+}
+
+/// Verify the second login step for an enrolled user
+pub async fn verify_second_factor(
+    db: &DatabaseConnection,
+    user_model: &user::Model,
+    factor: SecondFactor<'_>,
+) -> Result<(), AppError> {
+    if !user_model.totp_enabled {
+        return Err(AppError::bad_request("Two-factor authentication is not enabled"));
+    }
+
+    let verified = match (factor.code, factor.recovery_code) {
+        (Some(code), _) => consume_totp(db, user_model, code).await?,
+        (None, Some(recovery)) => consume_recovery_code(db, user_model.id, recovery).await?,
+        (None, None) => false,
+    };
+
+    if verified { Ok(()) } else { Err(invalid_code()) }
+}
+
+/// Store a new, not yet confirmed secret and return it with its `otpauth://` URI
+pub async fn begin_enrollment(
+    db: &DatabaseConnection,
+    user_model: user::Model,
+) -> Result<(String, String), AppError> {
+    if user_model.totp_enabled {
+        return Err(AppError::conflict("Two-factor authentication is already enabled"));
+    }
+
+    let secret = generate_secret()?;
+    let uri = otpauth_uri(&secret, &user_model.username);
+
+    let mut user = user_model.into_active_model();
+    user.totp_secret = Set(Some(secret.clone()));
+    user.totp_last_step = Set(None);
+    user.update(db).await?;
+
+    Ok((secret, uri))
+}
+
+/// Enable 2FA once the user proves the app is set up, and issue recovery codes
+pub async fn confirm_enrollment(
+    db: &DatabaseConnection,
+    user_model: user::Model,
+    code: &str,
+) -> Result<Vec<String>, AppError> {
+    if user_model.totp_enabled {
+        return Err(AppError::conflict("Two-factor authentication is already enabled"));
+    }
+    if user_model.totp_secret.is_none() {
+        return Err(AppError::bad_request("Start the two-factor setup first"));
+    }
+
+    let txn = db.begin().await?;
+    if !consume_totp(&txn, &user_model, code).await? {
+        return Err(invalid_code());
+    }
+
+    let user_id = user_model.id;
+    let mut user = user::Entity::find_by_id(user_id)
+        .one(&txn)
+        .await?
+        .ok_or_else(invalid_code)?
+        .into_active_model();
+    user.totp_enabled = Set(true);
+    user.update(&txn).await?;
+
+    let codes = replace_recovery_codes(&txn, user_id).await?;
+    txn.commit().await?;
+
+    Ok(codes)
+}
+
+/// Throw away the old recovery codes and store the hashes of a new set
+async fn replace_recovery_codes<C>(db: &C, user_id: Uuid) -> Result<Vec<String>, AppError>
+where
+    C: ConnectionTrait,
+{
+    recovery_code::Entity::delete_many()
+        .filter(recovery_code::Column::UserId.eq(user_id))
+        .exec(db)
+        .await?;
+
+    let codes = generate_recovery_codes()?;
+    let now = Utc::now();
+    let rows = codes.iter().map(|code| recovery_code::ActiveModel {
+        id: Set(Uuid::new_v4()),
+        user_id: Set(user_id),
+        code_hash: Set(hash_token(&normalize_recovery_code(code))),
+        created_at: Set(now),
+        ..Default::default()
+    });
+    recovery_code::Entity::insert_many(rows)
+        .exec(db)
+        .await?;
+
+    Ok(codes)
+}
+
+/// Turn 2FA off after checking a current code or a recovery code
+pub async fn disable(
+    db: &DatabaseConnection,
+    user_model: user::Model,
+    factor: SecondFactor<'_>,
+) -> Result<(), AppError> {
+    verify_second_factor(db, &user_model, factor).await?;
+
+    let user_id = user_model.id;
+    let txn = db.begin().await?;
+
+    let mut user = user::Entity::find_by_id(user_id)
+        .one(&txn)
+        .await?
+        .ok_or_else(invalid_code)?
+        .into_active_model();
+    user.totp_enabled = Set(false);
+    user.totp_secret = Set(None);
+    user.totp_last_step = Set(None);
+    user.update(&txn).await?;
+
+    recovery_code::Entity::delete_many()
+        .filter(recovery_code::Column::UserId.eq(user_id))
+        .exec(&txn)
+        .await?;
+
+    txn.commit().await?;
+    Ok(())
+}
@@ -1,124 +1,192 @@
-use axum::body::Body;
-use axum::extract::Query;
-use axum::http::StatusCode;
-use axum::response::{IntoResponse, Response};
-use serde::Deserialize;
-use std::fs::File;
-use std::io::{Read, Seek, SeekFrom};
+use anyhow::Context;
 use std::path::{Path, PathBuf};
-use slug::slugify;
 use tokio::fs;
 use tokio::fs::OpenOptions;
-use tokio::io::AsyncWriteExt;
+use axum::body::Bytes;
+use futures::{Stream, TryStreamExt};
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
 use tracing::info;
 use crate::app::files::validator::{path_is_valid, sanitize_filename};
-use crate::app::hashing::hash::hash_file;
+use crate::app::hashing::hash::FileHasher;
+use crate::error::AppError;
 
-const ALLOWED_EXTENSIONS: &[&str; 6] = &["png", "jpg", "jpeg", "gif", "mp4", "pdf"];
+const COPY_BUFFER: usize = 64 * 1024;
+/// How much of an upload `keep_head` keeps for MIME sniffing
+const HEAD_SIZE: usize = 8 * 1024;
 
-#[derive(Deserialize)]
-struct DownloadParams {
-    file_name: String,
-    offset: u64,
-    total_chunks: usize,
+/// Function that pointing to storage folder
+pub fn path_storage(sub_path: &str) -> PathBuf {
+    let base_path = std::env::current_dir().expect("Failed to get current directory");
+    base_path.join("storage").join(sub_path)
 }
 
-/// Function that downloads a file using a chunk mechanism
-pub fn download_file(Query(params): Query<DownloadParams>) -> impl IntoResponse {
-    let file_path = path_storage(&params.file_name);
-    let mut file = File::open(&file_path).unwrap_or_else(|_| {
-        panic!("Failed to open file: {}", file_path.display());
-    });
-    let mut buffer = vec![0; params.total_chunks];
-    file.seek(SeekFrom::Start(params.offset)).unwrap();
-    let bytes_read = file.read(&mut buffer).unwrap();
-
-    if bytes_read == 0 {
-        return StatusCode::NO_CONTENT.into_response();
+/// Where a finished upload is stored, relative to `storage/`:
+/// `uploads/<subdir>/<timestamp>_<random>_<sanitized name>`. The random part
+/// keeps two uploads of the same name in the same second apart.
+pub fn upload_path(file_name: &str, subdir: Option<&str>) -> String {
+    let timestamp = chrono::Utc::now().format("%Y-%m-%d_%H-%M-%S").to_string();
+    let sanitized_name = sanitize_filename(file_name);
+    let final_file_name = format!("{}_{}_{}", &timestamp, uuid::Uuid::new_v4().simple(), sanitized_name);
+
+    match subdir {
+        Some(subdir) => format!("uploads/{}/{}", subdir, final_file_name),
+        None => format!("uploads/{}", final_file_name)
     }
+}
 
-    let body = Body::from(buffer[..bytes_read].to_vec());
-    Response::builder()
-        .header("Content-Type", "application/octet-stream")
-        .header(
-            "Content-Disposition",
-            format!("attachment; filename={}", params.file_name),
-        )
-        .body(body)
-        .unwrap()
+/// Copy `reader` to `writer` a block at a time, feeding every block to `hasher`
+pub async fn copy_hashed<R, W>(reader: &mut R, writer: &mut W, hasher: &mut FileHasher) -> std::io::Result<u64>
+where
+    R: AsyncRead + Unpin,
+    W: AsyncWrite + Unpin,
+{
+    let mut buffer = vec![0u8; COPY_BUFFER];
+    let mut copied = 0;
+    loop {
+        let read = reader.read(&mut buffer).await?;
+        if read == 0 {
+            return Ok(copied);
+        }
+        hasher.update(&buffer[..read]);
+        writer.write_all(&buffer[..read]).await?;
+        copied += read as u64;
+    }
 }
 
-/// Function that pointing to storage folder
-pub fn path_storage(sub_path: &str) -> PathBuf {
-    let base_path = std::env::current_dir().expect("Failed to get current directory");
-    base_path.join("storage").join(sub_path)
+/// Write a request body or multipart field to `file` as it arrives, passing
+/// every piece to `inspect` first. Fails with 413 as soon as more than
+/// `max_bytes` came in. Returns the number of bytes written.
+pub async fn write_stream<S, E>(
+    mut stream: S,
+    file: &mut fs::File,
+    max_bytes: u64,
+    mut inspect: impl FnMut(&[u8]),
+) -> Result<u64, AppError>
+where
+    S: Stream<Item = Result<Bytes, E>> + Unpin,
+    AppError: From<E>,
+{
+    let mut written = 0u64;
+    while let Some(bytes) = stream.try_next().await? {
+        written += bytes.len() as u64;
+        if written > max_bytes {
+            return Err(AppError::PayloadTooLarge(format!("Upload is over {} bytes", max_bytes)));
+        }
+        inspect(&bytes);
+        file.write_all(&bytes).await.context("Failed to write upload")?;
+    }
+    file.flush().await.context("Failed to flush upload")?;
+    Ok(written)
+}
+
+/// Keep the first bytes of a stream in `head`, enough to sniff the file type
+pub fn keep_head(head: &mut Vec<u8>, bytes: &[u8]) {
+    let room = HEAD_SIZE.saturating_sub(head.len());
+    head.extend_from_slice(&bytes[..room.min(bytes.len())]);
+}
+
+/// A file under `storage/uploads/tmp` that is removed on drop unless it was
+/// moved somewhere with `persist`
+pub struct TempFile {
+    path: PathBuf,
+    pub file: fs::File,
+}
+
+impl TempFile {
+    pub async fn create() -> Result<Self, AppError> {
+        let dir = path_storage("uploads/tmp");
+        fs::create_dir_all(&dir).await.context("Failed to create temp dir")?;
+        let path = dir.join(format!("{}.part", uuid::Uuid::new_v4()));
+        let file = fs::File::create(&path).await.context("Failed to create temp file")?;
+        Ok(Self { path, file })
+    }
+
+    /// The first bytes of the file, enough to sniff its type
+    pub async fn head(&self) -> Result<Vec<u8>, AppError> {
+        let mut head = Vec::with_capacity(HEAD_SIZE);
+        fs::File::open(&self.path)
+            .await
+            .context("Failed to open temp file")?
+            .take(HEAD_SIZE as u64)
+            .read_to_end(&mut head)
+            .await
+            .context("Failed to read temp file")?;
+        Ok(head)
+    }
+
+    /// Move the file to `target`, replacing what is there
+    pub async fn persist(self, target: &Path) -> Result<(), AppError> {
+        fs::rename(&self.path, target).await.context("Failed to store upload")?;
+        Ok(())
+    }
+}
+
+impl Drop for TempFile {
+    fn drop(&mut self) {
+        // Already gone after persist
+        std::fs::remove_file(&self.path).ok();
+    }
 }
 
 /// Write a file as chunks
 pub async fn write_file(path: &str, file_name: &str, total_chunks: usize, output_out: Option<&str>)
-    -> Result<(String, String), (StatusCode, String)>
+    -> Result<(String, String), AppError>
 {
     if !path_is_valid(path) {
         info!("{:?}", path);
-        return Err((StatusCode::NO_CONTENT, "Invalid path".to_owned()));
+        return Err(AppError::bad_request("Invalid path"));
     }
 
-    let timestamp = chrono::Utc::now().format("%Y-%m-%d_%H-%M-%S").to_string();
-    let sanitized_name = sanitize_filename(&file_name);
-    let final_file_name = format!("{}_{}", &timestamp, sanitized_name);
-
     // let output_dir = Path::new(path).parent()
     //     .ok_or((StatusCode::INTERNAL_SERVER_ERROR, "Invalid chunk directory structure".to_string()))?; // keluar dari folder chunk
     // let output_file_name = output_dir.join(file_name).as_path().to_str().unwrap().to_string();
-    let relative_path = match output_out {
-        Some(subdir) => format!("uploads/{}/{}", subdir, final_file_name),
-        None => format!("uploads/{}", final_file_name)
-    };
+    let relative_path = upload_path(file_name, output_out);
 
     let output_path = path_storage(&relative_path);
     if let Some(parent) = output_path.parent() {
-        fs::create_dir_all(parent).await.map_err(|e| {
-            (StatusCode::INTERNAL_SERVER_ERROR, format!("Failed to create output dir: {}", e))
-        })?;
+        fs::create_dir_all(parent).await.context("Failed to create output dir")?;
     }
 
 
+    // Never over another upload
     let mut output_file = OpenOptions::new()
-        .create(true)
         .write(true)
-        .truncate(true)
+        .create_new(true)
         .open(&output_path).await
-        .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, format!("{:?}", e)))?;
-
-    for chunk_number in 0..total_chunks {
-        // let ck_path = format!("{}/chunk/{}", path, chunk_number);
-        let ck_path = format!("{}/{}", path, chunk_number);
-        let chunk_path = path_storage(&ck_path);
-        let chunk_data = fs::read(&chunk_path)
-            .await
-            .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, format!("Failed to read chunk {}: {}", chunk_number, e)))?;
-        output_file.write_all(&chunk_data)
-            .await
-            .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, format!("Failed to write chunk {}: {}", chunk_number, e)))?;
+        .context("Failed to create output file")?;
+
+    // Hashed while copying, so neither a chunk nor the whole file is held in memory
+    let mut hasher = FileHasher::new();
+    let copied = async {
+        for chunk_number in 0..total_chunks {
+            // let ck_path = format!("{}/chunk/{}", path, chunk_number);
+            let ck_path = format!("{}/{}", path, chunk_number);
+            let chunk_path = path_storage(&ck_path);
+            let mut chunk_file = fs::File::open(&chunk_path)
+                .await
+                .with_context(|| format!("Failed to read chunk {}", chunk_number))?;
+            copy_hashed(&mut chunk_file, &mut output_file, &mut hasher)
+                .await
+                .with_context(|| format!("Failed to write chunk {}", chunk_number))?;
+        }
+        output_file.flush().await.context("Failed to flush output file")
+    }
+    .await;
+    if let Err(err) = copied {
+        // Half a file is of no use to anyone
+        fs::remove_file(&output_path).await.ok();
+        return Err(err.into());
     }
 
     info!("{:?}", output_path);
-    info!("Entering hashes mode");
-    let file_bytes = fs::read(&output_path).await
-        .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, format!("Failed to read file: {}", e)))?;
-
-    let hash = hash_file(&file_bytes)
-        .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, format!("Failed to hash file: {}", e)))?;
+    let hash = hasher.finalize();
 
     info!("Entering hashes directory");
     let hash_file_path = output_path.with_extension("hash");
 
     fs::write(&hash_file_path, hash.as_bytes()).await
-        .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, format!("Failed to write hash file: {}", e)))?;
+        .context("Failed to write hash file")?;
 
-    fs::remove_dir_all(path_storage(path))
-        .await
-        .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, format!("Failed to remove directory: {}", e)))?;
     Ok((relative_path, hash))
 
 }
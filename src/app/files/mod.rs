@@ -1,2 +1,7 @@
+#[allow(clippy::module_inception)]
 pub mod files;
 pub mod validator;
+pub mod session;
+pub mod tus;
+pub mod policy;
+pub mod sweep;
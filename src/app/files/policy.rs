@@ -0,0 +1,309 @@
+use crate::app::files::validator::{
+    file_extension, ALLOWED_EXTENSIONS, ALLOWED_MIME_TYPES, MAX_AVATAR_SIZE, MAX_CHUNK_SIZE,
+    MAX_UPLOAD_SIZE,
+};
+use crate::error::AppError;
+use serde::{Deserialize, Serialize};
+
+/// Room left in a multipart body for the text fields next to one chunk
+const FORM_OVERHEAD: usize = 1024 * 1024;
+
+/// What an upload is for, each kind has its own `UploadPolicy`
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum UploadKind {
+    #[default]
+    File,
+    Book,
+    Avatar,
+    PostAttachment,
+}
+
+impl UploadKind {
+    pub const ALL: [Self; 4] = [Self::File, Self::Book, Self::Avatar, Self::PostAttachment];
+
+    pub fn parse(name: &str) -> Option<Self> {
+        Self::ALL.into_iter().find(|kind| kind.name() == name)
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::File => "file",
+            Self::Book => "book",
+            Self::Avatar => "avatar",
+            Self::PostAttachment => "post_attachment",
+        }
+    }
+
+    /// Directory under `storage/uploads` finished files go to
+    pub fn dir(self) -> &'static str {
+        match self {
+            Self::File => "files",
+            Self::Book => "books",
+            Self::Avatar => "avatars",
+            Self::PostAttachment => "attachments",
+        }
+    }
+
+    fn env_prefix(self) -> &'static str {
+        match self {
+            Self::File => "UPLOAD_FILE",
+            Self::Book => "UPLOAD_BOOK",
+            Self::Avatar => "UPLOAD_AVATAR",
+            Self::PostAttachment => "UPLOAD_POST_ATTACHMENT",
+        }
+    }
+}
+
+/// Limits for one kind of upload. The defaults come from `validator.rs`,
+/// `UPLOAD_<KIND>_MAX_SIZE`, `_CHUNK_SIZE`, `_EXTENSIONS` and `_MIME_TYPES`
+/// in `.env` override them.
+#[derive(Debug, Clone)]
+pub struct UploadPolicy {
+    pub kind: UploadKind,
+    /// Largest whole file, in bytes
+    pub max_size: u64,
+    /// Largest single chunk or multipart `chunkData`, in bytes
+    pub chunk_size: u64,
+    pub extensions: Vec<String>,
+    pub mime_types: Vec<String>,
+}
+
+impl UploadPolicy {
+    pub fn for_kind(kind: UploadKind) -> Self {
+        Self::from_lookup(kind, |name| std::env::var(name).ok())
+    }
+
+    /// `for_kind` with the overrides read through `lookup`, which gets the
+    /// full variable name, e.g. `UPLOAD_BOOK_MAX_SIZE`
+    pub fn from_lookup(kind: UploadKind, lookup: impl Fn(&str) -> Option<String>) -> Self {
+        let (max_size, extensions, mime_types): (u64, &[&str], &[&str]) = match kind {
+            UploadKind::File | UploadKind::PostAttachment => {
+                (MAX_UPLOAD_SIZE, ALLOWED_EXTENSIONS, ALLOWED_MIME_TYPES)
+            }
+            UploadKind::Book => (
+                MAX_UPLOAD_SIZE,
+                &["pdf", "epub"],
+                &["application/pdf", "application/epub+zip"],
+            ),
+            UploadKind::Avatar => (
+                MAX_AVATAR_SIZE,
+                &["png", "jpg", "jpeg", "gif"],
+                &["image/png", "image/jpeg", "image/gif"],
+            ),
+        };
+
+        let var = |name: &str| lookup(&format!("{}_{}", kind.env_prefix(), name));
+        let size = |name: &str, default: u64| {
+            var(name)
+                .and_then(|v| v.parse().ok())
+                .unwrap_or(default)
+                .max(1)
+        };
+        let list = |name: &str, default: &[&str]| match var(name) {
+            Some(v) => v
+                .split(',')
+                .map(|item| item.trim().to_lowercase())
+                .filter(|item| !item.is_empty())
+                .collect(),
+            None => default.iter().map(|item| item.to_string()).collect(),
+        };
+
+        let max_size = size("MAX_SIZE", max_size);
+        Self {
+            kind,
+            max_size,
+            chunk_size: size("CHUNK_SIZE", MAX_CHUNK_SIZE).min(max_size),
+            extensions: list("EXTENSIONS", extensions),
+            mime_types: list("MIME_TYPES", mime_types),
+        }
+    }
+
+    /// Limit for a `DefaultBodyLimit` layer in front of a multipart chunk upload
+    pub fn body_limit(&self) -> usize {
+        self.chunk_size as usize + FORM_OVERHEAD
+    }
+
+    pub fn check_size(&self, size: u64) -> Result<(), AppError> {
+        if size > self.max_size {
+            return Err(AppError::PayloadTooLarge(format!(
+                "A {} upload can't be over {} bytes",
+                self.kind.name(),
+                self.max_size
+            )));
+        }
+        Ok(())
+    }
+
+    /// A file of `total_chunks` chunks of up to `chunk_size` bytes has to be
+    /// able to fit in `max_size`, anything more is refused up front
+    pub fn check_chunk_count(&self, total_chunks: usize) -> Result<(), AppError> {
+        let most = self.max_size.div_ceil(self.chunk_size);
+        if total_chunks as u64 > most {
+            return Err(AppError::PayloadTooLarge(format!(
+                "A {} upload can't have more than {} chunks",
+                self.kind.name(),
+                most
+            )));
+        }
+        Ok(())
+    }
+
+    pub fn check_file_name(&self, file_name: &str) -> Result<(), AppError> {
+        match file_extension(file_name) {
+            Some(ext) if self.extensions.contains(&ext) => Ok(()),
+            _ => Err(AppError::UnsupportedMediaType(format!(
+                "A {} upload must end in one of: {}",
+                self.kind.name(),
+                self.extensions.join(", ")
+            ))),
+        }
+    }
+
+    /// `head` is the start of the file. Its detected type has to be allowed
+    /// and agree with the extension of `file_name`, so a renamed file is refused.
+    pub fn check_content(&self, file_name: &str, head: &[u8]) -> Result<(), AppError> {
+        self.check_file_name(file_name)?;
+
+        let detected = infer::get(head)
+            .ok_or_else(|| AppError::UnsupportedMediaType("Unrecognised file content".to_string()))?;
+        if !self.mime_types.iter().any(|mime| mime == detected.mime_type()) {
+            return Err(AppError::UnsupportedMediaType(format!(
+                "{} is not allowed for a {} upload",
+                detected.mime_type(),
+                self.kind.name()
+            )));
+        }
+
+        let ext = file_extension(file_name).unwrap_or_default();
+        if normalize_extension(&ext) != normalize_extension(detected.extension()) {
+            return Err(AppError::UnsupportedMediaType(format!(
+                "File content is {} but the name ends in .{}",
+                detected.mime_type(),
+                ext
+            )));
+        }
+        Ok(())
+    }
+}
+
+/// Extensions that name the same type
+fn normalize_extension(ext: &str) -> &str {
+    match ext {
+        "jpeg" => "jpg",
+        other => other,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::http::StatusCode;
+    use std::collections::HashMap;
+
+    const PNG: &[u8] = b"\x89PNG\r\n\x1a\n\0\0\0\rIHDR";
+    const JPEG: &[u8] = b"\xFF\xD8\xFF\xE0\0\x10JFIF\0";
+    const PDF: &[u8] = b"%PDF-1.4\n";
+
+    fn avatar() -> UploadPolicy {
+        UploadPolicy {
+            kind: UploadKind::Avatar,
+            max_size: 100,
+            chunk_size: 100,
+            extensions: vec!["png".into(), "jpg".into(), "jpeg".into()],
+            mime_types: vec!["image/png".into(), "image/jpeg".into()],
+        }
+    }
+
+    fn status(result: Result<(), AppError>) -> StatusCode {
+        result.unwrap_err().status()
+    }
+
+    fn defaults(kind: UploadKind) -> UploadPolicy {
+        UploadPolicy::from_lookup(kind, |_| None)
+    }
+
+    #[test]
+    fn env_overrides_the_defaults() {
+        let env = HashMap::from([
+            ("UPLOAD_AVATAR_MAX_SIZE", "1000"),
+            ("UPLOAD_AVATAR_CHUNK_SIZE", "4000"),
+            ("UPLOAD_AVATAR_EXTENSIONS", " PNG, webp ,"),
+            // Another kind's settings don't leak over
+            ("UPLOAD_BOOK_MIME_TYPES", "text/plain"),
+        ]);
+        let policy = UploadPolicy::from_lookup(UploadKind::Avatar, |name| env.get(name).map(|v| v.to_string()));
+
+        assert_eq!(policy.max_size, 1000);
+        // A chunk is never bigger than the whole file
+        assert_eq!(policy.chunk_size, 1000);
+        assert_eq!(policy.extensions, vec!["png", "webp"]);
+        assert_eq!(policy.mime_types, vec!["image/png", "image/jpeg", "image/gif"]);
+    }
+
+    #[test]
+    fn unparsable_sizes_keep_the_default() {
+        let policy = UploadPolicy::from_lookup(UploadKind::Book, |name| {
+            name.ends_with("_MAX_SIZE").then(|| "lots".to_string())
+        });
+        assert_eq!(policy.max_size, MAX_UPLOAD_SIZE);
+    }
+
+    #[test]
+    fn defaults_differ_per_kind() {
+        let book = defaults(UploadKind::Book);
+        assert_eq!(book.extensions, vec!["pdf", "epub"]);
+        assert_eq!(book.max_size, MAX_UPLOAD_SIZE);
+    }
+
+    #[test]
+    fn books_and_files_take_500_mb() {
+        for kind in [UploadKind::Book, UploadKind::File] {
+            let policy = defaults(kind);
+            assert_eq!(policy.max_size, 500 * 1024 * 1024);
+            assert!(policy.check_size(500 * 1024 * 1024).is_ok());
+        }
+    }
+
+    #[test]
+    fn size_up_to_the_max_is_allowed() {
+        assert!(avatar().check_size(100).is_ok());
+        assert_eq!(status(avatar().check_size(101)), StatusCode::PAYLOAD_TOO_LARGE);
+    }
+
+    #[test]
+    fn file_name_needs_an_allowed_extension() {
+        assert!(avatar().check_file_name("me.png").is_ok());
+        assert!(avatar().check_file_name("ME.PNG").is_ok());
+        assert_eq!(status(avatar().check_file_name("me.gif")), StatusCode::UNSUPPORTED_MEDIA_TYPE);
+        assert_eq!(status(avatar().check_file_name("png")), StatusCode::UNSUPPORTED_MEDIA_TYPE);
+    }
+
+    #[test]
+    fn content_matching_the_name_is_allowed() {
+        assert!(avatar().check_content("me.png", PNG).is_ok());
+        assert!(avatar().check_content("me.jpg", JPEG).is_ok());
+    }
+
+    #[test]
+    fn jpeg_and_jpg_are_the_same_type() {
+        assert!(avatar().check_content("me.jpeg", JPEG).is_ok());
+    }
+
+    #[test]
+    fn renamed_file_is_refused() {
+        assert_eq!(status(avatar().check_content("me.png", JPEG)), StatusCode::UNSUPPORTED_MEDIA_TYPE);
+        assert_eq!(status(avatar().check_content("me.jpg", PNG)), StatusCode::UNSUPPORTED_MEDIA_TYPE);
+    }
+
+    #[test]
+    fn content_type_must_be_allowed() {
+        // A real PDF, but named and sniffed as something avatars don't take
+        assert_eq!(status(avatar().check_content("me.png", PDF)), StatusCode::UNSUPPORTED_MEDIA_TYPE);
+    }
+
+    #[test]
+    fn unrecognised_content_is_refused() {
+        assert_eq!(status(avatar().check_content("me.png", b"plain text")), StatusCode::UNSUPPORTED_MEDIA_TYPE);
+    }
+}
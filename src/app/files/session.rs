@@ -0,0 +1,407 @@
+use crate::app::files::files::{path_storage, write_file, TempFile};
+use crate::app::files::policy::{UploadKind, UploadPolicy};
+use crate::app::files::validator::sanitize_filename;
+use crate::app::hashing::hash::Checksum;
+use crate::error::AppError;
+use anyhow::Context;
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeSet;
+use std::io::ErrorKind;
+use std::time::{Duration, SystemTime};
+use tokio::fs;
+use uuid::Uuid;
+
+/// A chunked upload in progress, kept under `storage/uploads/sessions/<id>/`.
+/// `session.json` only holds what the client declared at init; the received
+/// chunks are the files in `chunk/`, each with its verified digest in `digest/`.
+/// Nothing lives in memory, so a client can resume after a restart and parallel
+/// chunk requests can't overwrite each other.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct UploadSession {
+    pub id: Uuid,
+    pub user_id: Uuid,
+    /// Sessions written before kinds existed are plain files
+    #[serde(default)]
+    pub kind: UploadKind,
+    pub file_name: String,
+    pub total_chunks: usize,
+    pub created_at: DateTime<Utc>,
+}
+
+/// What the client needs to know to resume
+#[derive(Debug, Serialize)]
+pub struct SessionStatus {
+    pub id: Uuid,
+    pub file_name: String,
+    pub total_chunks: usize,
+    pub received: Vec<usize>,
+    pub missing: Vec<usize>,
+    pub complete: bool,
+}
+
+fn not_found() -> AppError {
+    AppError::not_found("Upload session not found")
+}
+
+/// How long a session may go without a new chunk before the sweep removes it
+pub fn session_ttl() -> Duration {
+    let secs = std::env::var("UPLOAD_SESSION_TTL")
+        .ok()
+        .and_then(|v| v.parse().ok())
+        .unwrap_or(86400);
+    Duration::from_secs(secs)
+}
+
+impl UploadSession {
+    fn dir_of(id: Uuid) -> String {
+        format!("uploads/sessions/{}", id)
+    }
+
+    fn manifest_of(id: Uuid) -> String {
+        format!("{}/session.json", Self::dir_of(id))
+    }
+
+    fn chunk_dir(&self) -> String {
+        format!("{}/chunk", Self::dir_of(self.id))
+    }
+
+    fn digest_dir(&self) -> String {
+        format!("{}/digest", Self::dir_of(self.id))
+    }
+
+    /// Open a session for `total_chunks` chunks. No chunk can be over the
+    /// policy's `chunk_size`, so a session that can't add up to more than
+    /// `max_size` needs no size check per chunk.
+    pub async fn create(user_id: Uuid, kind: UploadKind, file_name: &str, total_chunks: usize) -> Result<Self, AppError> {
+        let file_name = sanitize_filename(file_name);
+        let policy = UploadPolicy::for_kind(kind);
+        policy.check_file_name(&file_name)?;
+        policy.check_chunk_count(total_chunks)?;
+
+        let session = Self {
+            id: Uuid::new_v4(),
+            user_id,
+            kind,
+            file_name,
+            total_chunks,
+            created_at: Utc::now(),
+        };
+
+        for dir in [session.chunk_dir(), session.digest_dir()] {
+            fs::create_dir_all(path_storage(&dir))
+                .await
+                .context("Failed to create upload session directory")?;
+        }
+        let manifest = serde_json::to_vec_pretty(&session).context("Failed to encode upload session")?;
+        fs::write(path_storage(&Self::manifest_of(session.id)), manifest)
+            .await
+            .context("Failed to write upload session")?;
+
+        Ok(session)
+    }
+
+    /// Session `id` if it belongs to `user_id`, someone else's session is reported as missing
+    pub async fn load(id: Uuid, user_id: Uuid) -> Result<Self, AppError> {
+        let raw = match fs::read(path_storage(&Self::manifest_of(id))).await {
+            Ok(raw) => raw,
+            Err(err) if err.kind() == ErrorKind::NotFound => return Err(not_found()),
+            Err(err) => return Err(err.into()),
+        };
+        let session: Self = serde_json::from_slice(&raw).context("Corrupt upload session")?;
+
+        if session.user_id != user_id {
+            return Err(not_found());
+        }
+        Ok(session)
+    }
+
+    pub fn policy(&self) -> UploadPolicy {
+        UploadPolicy::for_kind(self.kind)
+    }
+
+    /// Store chunk `index` from where it was streamed to. `digest` is the hex
+    /// hash of the chunk, checked against the client's `checksum` when it sent
+    /// one. Chunks may come in any order; sending a stored chunk again is a
+    /// no-op when it is the same data and a conflict when it isn't. The digest
+    /// is written before the rename, so a chunk never counts as received
+    /// without one. Chunk 0 has to start like the file it claims to be.
+    pub async fn store_chunk(
+        &self,
+        index: usize,
+        chunk: TempFile,
+        digest: &str,
+        checksum: Option<&Checksum>,
+    ) -> Result<(), AppError> {
+        if index >= self.total_chunks {
+            return Err(AppError::bad_request(format!(
+                "Chunk index must be below {}",
+                self.total_chunks
+            )));
+        }
+        if let Some(checksum) = checksum {
+            checksum.verify(digest)?;
+        }
+        let policy = self.policy();
+        if index == 0 {
+            policy.check_content(&self.file_name, &chunk.head().await?)?;
+        }
+
+        let target = path_storage(&self.chunk_dir()).join(index.to_string());
+        let digest_path = path_storage(&self.digest_dir()).join(index.to_string());
+        if fs::try_exists(&target).await? {
+            return match fs::read_to_string(&digest_path).await {
+                Ok(stored) if stored == digest => Ok(()),
+                Ok(_) => Err(AppError::conflict(format!(
+                    "Chunk {} was already received with different content",
+                    index
+                ))),
+                Err(err) => Err(err.into()),
+            };
+        }
+
+        fs::write(&digest_path, digest)
+            .await
+            .context("Failed to write chunk digest")?;
+        chunk.persist(&target).await
+    }
+
+    pub async fn received(&self) -> Result<BTreeSet<usize>, AppError> {
+        let mut entries = match fs::read_dir(path_storage(&self.chunk_dir())).await {
+            Ok(entries) => entries,
+            Err(err) if err.kind() == ErrorKind::NotFound => return Err(not_found()),
+            Err(err) => return Err(err.into()),
+        };
+
+        let mut received = BTreeSet::new();
+        while let Some(entry) = entries.next_entry().await? {
+            let index = entry.file_name().to_str().and_then(|name| name.parse::<usize>().ok());
+            if let Some(index) = index.filter(|index| *index < self.total_chunks) {
+                received.insert(index);
+            }
+        }
+        Ok(received)
+    }
+
+    /// Bytes in the chunks received so far
+    pub async fn stored_size(&self) -> Result<u64, AppError> {
+        let dir = path_storage(&self.chunk_dir());
+        let mut size = 0;
+        for index in self.received().await? {
+            size += fs::metadata(dir.join(index.to_string())).await?.len();
+        }
+        Ok(size)
+    }
+
+    pub async fn status(&self) -> Result<SessionStatus, AppError> {
+        let received = self.received().await?;
+        let missing = (0..self.total_chunks)
+            .filter(|index| !received.contains(index))
+            .collect::<Vec<_>>();
+
+        Ok(SessionStatus {
+            id: self.id,
+            file_name: self.file_name.clone(),
+            total_chunks: self.total_chunks,
+            complete: missing.is_empty(),
+            received: received.into_iter().collect(),
+            missing,
+        })
+    }
+
+    /// Join the chunks into the directory of the session's kind, only once
+    /// every index from 0 to `total_chunks - 1` is stored. The session is kept
+    /// until [`Self::forget`], so [`Self::restore`] can reopen it when storing
+    /// what the file belongs to fails. Returns the stored path relative to
+    /// `storage/` and the file hash.
+    pub async fn complete(&self) -> Result<(String, String), AppError> {
+        let status = self.status().await?;
+        if !status.complete {
+            return Err(AppError::conflict(format!(
+                "Upload is missing chunks {:?}",
+                status.missing
+            )));
+        }
+        self.policy().check_size(self.stored_size().await?)?;
+
+        // Taking the manifest away claims the session, a second complete gets a 404
+        let manifest = path_storage(&Self::manifest_of(self.id));
+        match fs::rename(&manifest, manifest.with_extension("completing")).await {
+            Ok(()) => {}
+            Err(err) if err.kind() == ErrorKind::NotFound => return Err(not_found()),
+            Err(err) => return Err(err.into()),
+        }
+
+        match write_file(&self.chunk_dir(), &self.file_name, self.total_chunks, Some(self.kind.dir())).await {
+            Ok(stored) => Ok(stored),
+            Err(err) => {
+                // The chunks are still there, give the session back so complete can be retried
+                self.reopen().await?;
+                Err(err)
+            }
+        }
+    }
+
+    async fn reopen(&self) -> Result<(), AppError> {
+        let manifest = path_storage(&Self::manifest_of(self.id));
+        fs::rename(manifest.with_extension("completing"), &manifest)
+            .await
+            .context("Failed to restore upload session")?;
+        Ok(())
+    }
+
+    /// Undo [`Self::complete`]: drop the joined file at `relative_path` and
+    /// make the session resumable again, its chunks are still there
+    pub async fn restore(&self, relative_path: &str) -> Result<(), AppError> {
+        let output_path = path_storage(relative_path);
+        fs::remove_file(&output_path).await.ok();
+        fs::remove_file(output_path.with_extension("hash")).await.ok();
+        self.reopen().await
+    }
+
+    /// Remove a completed session, its file now belongs elsewhere
+    pub async fn forget(&self) {
+        fs::remove_dir_all(path_storage(&Self::dir_of(self.id))).await.ok();
+    }
+
+    /// Remove the sessions under `storage/uploads/sessions/` that got no chunk
+    /// for `ttl`, a client that gave up never calls discard. Returns how many
+    /// were removed.
+    pub async fn sweep_expired(ttl: Duration) -> Result<usize, AppError> {
+        let mut entries = match fs::read_dir(path_storage("uploads/sessions")).await {
+            Ok(entries) => entries,
+            Err(err) if err.kind() == ErrorKind::NotFound => return Ok(0),
+            Err(err) => return Err(err.into()),
+        };
+
+        let now = SystemTime::now();
+        let mut removed = 0;
+        while let Some(entry) = entries.next_entry().await? {
+            let dir = entry.path();
+            // A chunk landing in `chunk/` touches it, the directory itself dates from create
+            let mut touched = entry.metadata().await?.modified()?;
+            if let Ok(chunks) = fs::metadata(dir.join("chunk")).await {
+                touched = touched.max(chunks.modified()?);
+            }
+            let idle = now.duration_since(touched).unwrap_or_default();
+            if idle > ttl && fs::remove_dir_all(&dir).await.is_ok() {
+                removed += 1;
+            }
+        }
+        Ok(removed)
+    }
+
+    /// Drop the session and every chunk received so far
+    pub async fn discard(&self) -> Result<(), AppError> {
+        match fs::remove_dir_all(path_storage(&Self::dir_of(self.id))).await {
+            Ok(()) => Ok(()),
+            Err(err) if err.kind() == ErrorKind::NotFound => Err(not_found()),
+            Err(err) => Err(err.into()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::app::hashing::hash::FileHasher;
+    use axum::http::StatusCode;
+    use tokio::io::AsyncWriteExt;
+
+    const PDF_START: &[u8] = b"%PDF-1.4\n";
+
+    async fn temp_with(data: &[u8]) -> (TempFile, String) {
+        let mut temp = TempFile::create().await.unwrap();
+        temp.file.write_all(data).await.unwrap();
+        temp.file.flush().await.unwrap();
+
+        let mut hasher = FileHasher::new();
+        hasher.update(data);
+        (temp, hasher.finalize())
+    }
+
+    async fn store(session: &UploadSession, index: usize, data: &[u8]) -> Result<(), AppError> {
+        let (chunk, digest) = temp_with(data).await;
+        session.store_chunk(index, chunk, &digest, None).await
+    }
+
+    async fn session(total_chunks: usize) -> UploadSession {
+        UploadSession::create(Uuid::new_v4(), UploadKind::File, "notes.pdf", total_chunks)
+            .await
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn same_chunk_twice_is_a_no_op() {
+        let session = session(2).await;
+        store(&session, 1, b"second part").await.unwrap();
+        store(&session, 1, b"second part").await.unwrap();
+
+        assert_eq!(session.status().await.unwrap().received, vec![1]);
+        session.discard().await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn other_data_for_a_stored_chunk_conflicts() {
+        let session = session(2).await;
+        store(&session, 1, b"second part").await.unwrap();
+        let err = store(&session, 1, b"something else").await.unwrap_err();
+
+        assert_eq!(err.status(), StatusCode::CONFLICT);
+        session.discard().await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn chunks_in_any_order_join_in_index_order() {
+        let session = session(3).await;
+        store(&session, 2, b" end").await.unwrap();
+        assert!(!session.status().await.unwrap().complete);
+        let err = session.complete().await.unwrap_err();
+        assert_eq!(err.status(), StatusCode::CONFLICT);
+
+        store(&session, 0, PDF_START).await.unwrap();
+        store(&session, 1, b"middle").await.unwrap();
+        assert_eq!(session.status().await.unwrap().missing, Vec::<usize>::new());
+
+        let (path, hash) = session.complete().await.unwrap();
+        let stored = fs::read(path_storage(&path)).await.unwrap();
+        fs::remove_file(path_storage(&path)).await.unwrap();
+        fs::remove_file(path_storage(&path).with_extension("hash")).await.unwrap();
+        session.forget().await;
+
+        let expected = [PDF_START, b"middle", b" end"].concat();
+        assert_eq!(stored, expected);
+        assert_eq!(hash, temp_with(&expected).await.1);
+        assert!(UploadSession::load(session.id, session.user_id).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn restored_session_can_complete_again() {
+        let session = session(2).await;
+        store(&session, 0, PDF_START).await.unwrap();
+        store(&session, 1, b"rest").await.unwrap();
+
+        let (path, _) = session.complete().await.unwrap();
+        // A second complete while the first one holds the session
+        assert_eq!(session.complete().await.unwrap_err().status(), StatusCode::NOT_FOUND);
+        session.restore(&path).await.unwrap();
+        assert!(!fs::try_exists(path_storage(&path)).await.unwrap());
+
+        let session = UploadSession::load(session.id, session.user_id).await.unwrap();
+        let (path, _) = session.complete().await.unwrap();
+        fs::remove_file(path_storage(&path)).await.unwrap();
+        fs::remove_file(path_storage(&path).with_extension("hash")).await.unwrap();
+        session.forget().await;
+    }
+
+    #[tokio::test]
+    async fn more_chunks_than_the_policy_allows_are_refused() {
+        let policy = UploadPolicy::for_kind(UploadKind::File);
+        let too_many = policy.max_size.div_ceil(policy.chunk_size) as usize + 1;
+        let err = UploadSession::create(Uuid::new_v4(), UploadKind::File, "notes.pdf", too_many)
+            .await
+            .unwrap_err();
+
+        assert_eq!(err.status(), StatusCode::PAYLOAD_TOO_LARGE);
+    }
+}
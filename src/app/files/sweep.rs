@@ -0,0 +1,25 @@
+use crate::app::files::session::{session_ttl, UploadSession};
+use crate::app::files::tus::{upload_ttl, TusUpload};
+use std::time::Duration;
+use tracing::{info, warn};
+
+/// How often abandoned uploads are looked for
+const SWEEP_EVERY: Duration = Duration::from_secs(3600);
+
+/// Remove abandoned uploads every hour for as long as the server runs
+pub async fn sweep_uploads() {
+    let mut interval = tokio::time::interval(SWEEP_EVERY);
+    loop {
+        interval.tick().await;
+        match UploadSession::sweep_expired(session_ttl()).await {
+            Ok(0) => {}
+            Ok(removed) => info!("Removed {} abandoned upload sessions", removed),
+            Err(err) => warn!("Could not sweep upload sessions: {:?}", err),
+        }
+        match TusUpload::sweep_expired(upload_ttl()).await {
+            Ok(0) => {}
+            Ok(removed) => info!("Removed {} expired tus uploads", removed),
+            Err(err) => warn!("Could not sweep tus uploads: {:?}", err),
+        }
+    }
+}
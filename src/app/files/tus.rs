@@ -0,0 +1,312 @@
+use crate::app::files::files::{keep_head, path_storage, upload_path, write_stream};
+use crate::app::files::validator::sanitize_filename;
+use crate::app::hashing::hash::{hash_file_at, Checksum, FileHasher};
+use crate::error::AppError;
+use anyhow::Context;
+use axum::body::Bytes;
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine;
+use chrono::{DateTime, Utc};
+use futures::Stream;
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
+use std::io::ErrorKind;
+use std::sync::{LazyLock, Mutex};
+use std::time::{Duration, SystemTime};
+use tokio::fs::{self, OpenOptions};
+use uuid::Uuid;
+
+pub const TUS_VERSION: &str = "1.0.0";
+pub const TUS_EXTENSIONS: &str = "creation,termination,checksum,expiration";
+
+/// Uploads a request is currently writing to
+static BUSY: LazyLock<Mutex<HashSet<Uuid>>> = LazyLock::new(Default::default);
+
+/// Exclusive access to one upload for the length of a request, so two
+/// PATCHes can't both append at the same offset
+pub struct UploadLock(Uuid);
+
+impl UploadLock {
+    pub fn acquire(id: Uuid) -> Result<Self, AppError> {
+        let mut busy = BUSY.lock().map_err(|_| AppError::internal("Upload lock poisoned"))?;
+        if !busy.insert(id) {
+            return Err(AppError::conflict("Upload is being written by another request"));
+        }
+        Ok(Self(id))
+    }
+}
+
+impl Drop for UploadLock {
+    fn drop(&mut self) {
+        if let Ok(mut busy) = BUSY.lock() {
+            busy.remove(&self.0);
+        }
+    }
+}
+
+/// A tus upload, kept as `storage/uploads/tus/<id>.json` next to the bytes
+/// received so far in `<id>.bin`. The offset is the size of `<id>.bin`, so
+/// uploads resume across restarts.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TusUpload {
+    pub id: Uuid,
+    pub user_id: Uuid,
+    pub length: u64,
+    /// `Upload-Metadata` as the client sent it, echoed back on HEAD
+    pub raw_metadata: Option<String>,
+    pub metadata: HashMap<String, String>,
+    pub created_at: DateTime<Utc>,
+}
+
+fn not_found() -> AppError {
+    AppError::not_found("Upload not found")
+}
+
+/// How long an upload may go without a PATCH before the sweep removes it
+pub fn upload_ttl() -> Duration {
+    let secs = std::env::var("UPLOAD_TUS_TTL")
+        .ok()
+        .and_then(|v| v.parse().ok())
+        .unwrap_or(86400);
+    Duration::from_secs(secs)
+}
+
+/// `Upload-Metadata`: comma separated `key base64(value)` pairs, the value may be left out
+pub fn parse_metadata(header: &str) -> Result<HashMap<String, String>, AppError> {
+    let mut metadata = HashMap::new();
+    for pair in header.split(',').map(str::trim).filter(|pair| !pair.is_empty()) {
+        let (key, value) = pair.split_once(' ').unwrap_or((pair, ""));
+        let value = STANDARD
+            .decode(value.trim())
+            .ok()
+            .and_then(|value| String::from_utf8(value).ok())
+            .ok_or_else(|| AppError::bad_request(format!("Upload-Metadata value of '{}' is not base64", key)))?;
+        metadata.insert(key.to_string(), value);
+    }
+    Ok(metadata)
+}
+
+impl TusUpload {
+    fn info_path(id: Uuid) -> String {
+        format!("uploads/tus/{}.json", id)
+    }
+
+    fn data_path(id: Uuid) -> String {
+        format!("uploads/tus/{}.bin", id)
+    }
+
+    pub async fn create(user_id: Uuid, length: u64, raw_metadata: Option<String>) -> Result<Self, AppError> {
+        let metadata = match &raw_metadata {
+            Some(raw) => parse_metadata(raw)?,
+            None => HashMap::new(),
+        };
+        let upload = Self {
+            id: Uuid::new_v4(),
+            user_id,
+            length,
+            raw_metadata,
+            metadata,
+            created_at: Utc::now(),
+        };
+
+        let data_path = path_storage(&Self::data_path(upload.id));
+        if let Some(parent) = data_path.parent() {
+            fs::create_dir_all(parent).await.context("Failed to create tus directory")?;
+        }
+        fs::write(&data_path, []).await.context("Failed to create upload file")?;
+        let info = serde_json::to_vec_pretty(&upload).context("Failed to encode upload")?;
+        fs::write(path_storage(&Self::info_path(upload.id)), info)
+            .await
+            .context("Failed to write upload info")?;
+
+        Ok(upload)
+    }
+
+    /// Upload `id` if it belongs to `user_id`, someone else's upload is reported as missing
+    pub async fn load(id: Uuid, user_id: Uuid) -> Result<Self, AppError> {
+        let raw = match fs::read(path_storage(&Self::info_path(id))).await {
+            Ok(raw) => raw,
+            Err(err) if err.kind() == ErrorKind::NotFound => return Err(not_found()),
+            Err(err) => return Err(err.into()),
+        };
+        let upload: Self = serde_json::from_slice(&raw).context("Corrupt upload info")?;
+
+        if upload.user_id != user_id {
+            return Err(not_found());
+        }
+        Ok(upload)
+    }
+
+    /// Name the client gave the file in its `filename` metadata
+    pub fn file_name(&self) -> String {
+        sanitize_filename(self.metadata.get("filename").map(String::as_str).unwrap_or("file"))
+    }
+
+    /// `Upload-Expires`: the last write to the upload plus [`upload_ttl`], as an HTTP date
+    pub async fn expires(&self) -> Result<String, AppError> {
+        let touched = match fs::metadata(path_storage(&Self::data_path(self.id))).await {
+            Ok(meta) => meta.modified()?,
+            Err(err) if err.kind() == ErrorKind::NotFound => return Err(not_found()),
+            Err(err) => return Err(err.into()),
+        };
+        let expires: DateTime<Utc> = (touched + upload_ttl()).into();
+        Ok(expires.format("%a, %d %b %Y %H:%M:%S GMT").to_string())
+    }
+
+    pub async fn offset(&self) -> Result<u64, AppError> {
+        match fs::metadata(path_storage(&Self::data_path(self.id))).await {
+            Ok(meta) => Ok(meta.len()),
+            Err(err) if err.kind() == ErrorKind::NotFound => Err(not_found()),
+            Err(err) => Err(err.into()),
+        }
+    }
+
+    /// Stream a PATCH body onto the upload at `offset`, which has to be where
+    /// the upload currently ends. `check_head` sees the first bytes of the body.
+    /// When the checksum or `check_head` fails the file is cut back to `offset`,
+    /// so the client can resend the same piece. Returns the new offset.
+    pub async fn append<S, E>(
+        &self,
+        offset: u64,
+        body: S,
+        checksum: Option<Checksum>,
+        check_head: impl FnOnce(&[u8]) -> Result<(), AppError>,
+    ) -> Result<u64, AppError>
+    where
+        S: Stream<Item = Result<Bytes, E>> + Unpin,
+        AppError: From<E>,
+    {
+        let current = self.offset().await?;
+        if offset != current {
+            return Err(AppError::conflict(format!("Upload-Offset must be {}", current)));
+        }
+
+        let mut file = OpenOptions::new()
+            .append(true)
+            .open(path_storage(&Self::data_path(self.id)))
+            .await
+            .context("Failed to open upload file")?;
+
+        let mut hasher = FileHasher::new();
+        let mut head = Vec::new();
+        let written = write_stream(body, &mut file, self.length - current, |bytes| {
+            hasher.update(bytes);
+            keep_head(&mut head, bytes);
+        })
+        .await;
+
+        // A dropped connection keeps what arrived so the client can resume after
+        // it, unless a checksum or the file signature still has to vouch for it
+        if written.is_err() && checksum.is_none() && current > 0 {
+            return written;
+        }
+
+        let checked = written.and_then(|written| {
+            if let Some(checksum) = &checksum {
+                checksum.verify(&hasher.finalize())?;
+            }
+            if written > 0 {
+                check_head(&head)?;
+            }
+            Ok(written)
+        });
+        match checked {
+            Ok(written) => Ok(current + written),
+            Err(err) => {
+                file.set_len(current).await.context("Failed to roll back upload")?;
+                Err(err)
+            }
+        }
+    }
+
+    /// Move the finished file to `storage/uploads/<output_dir>/` and write its
+    /// `.hash` next to it. The upload is still known until [`Self::forget`],
+    /// so [`Self::restore`] can take the file back when storing what it
+    /// belongs to fails. Returns the stored path relative to `storage/`, the
+    /// file hash and the size.
+    pub async fn finish(&self, output_dir: &str) -> Result<(String, String, u64), AppError> {
+        let relative_path = upload_path(&self.file_name(), Some(output_dir));
+        let output_path = path_storage(&relative_path);
+        if let Some(parent) = output_path.parent() {
+            fs::create_dir_all(parent).await.context("Failed to create output dir")?;
+        }
+
+        // A link fails instead of replacing a file that is already there
+        let data_path = path_storage(&Self::data_path(self.id));
+        fs::hard_link(&data_path, &output_path)
+            .await
+            .context("Failed to move finished upload")?;
+        fs::remove_file(&data_path).await.context("Failed to move finished upload")?;
+
+        let hash = hash_file_at(&output_path).await.context("Failed to hash file")?;
+        fs::write(output_path.with_extension("hash"), hash.as_bytes())
+            .await
+            .context("Failed to write hash file")?;
+
+        Ok((relative_path, hash, self.length))
+    }
+
+    /// Undo [`Self::finish`]: put the file at `relative_path` back as the
+    /// upload's data, so the client can retry with an empty PATCH at the end
+    pub async fn restore(&self, relative_path: &str) -> Result<(), AppError> {
+        let output_path = path_storage(relative_path);
+        fs::rename(&output_path, path_storage(&Self::data_path(self.id)))
+            .await
+            .context("Failed to restore upload")?;
+        fs::remove_file(output_path.with_extension("hash")).await.ok();
+        Ok(())
+    }
+
+    /// Drop the info of a finished upload, its file now belongs elsewhere
+    pub async fn forget(&self) {
+        fs::remove_file(path_storage(&Self::info_path(self.id))).await.ok();
+    }
+
+    /// Remove the uploads under `storage/uploads/tus/` that got no PATCH for
+    /// `ttl`, along with data whose info is gone. Returns how many were removed.
+    pub async fn sweep_expired(ttl: Duration) -> Result<usize, AppError> {
+        let mut entries = match fs::read_dir(path_storage("uploads/tus")).await {
+            Ok(entries) => entries,
+            Err(err) if err.kind() == ErrorKind::NotFound => return Ok(0),
+            Err(err) => return Err(err.into()),
+        };
+
+        let now = SystemTime::now();
+        let mut removed = 0;
+        while let Some(entry) = entries.next_entry().await? {
+            let path = entry.path();
+            let Some(id) = path
+                .file_stem()
+                .and_then(|stem| stem.to_str())
+                .and_then(|stem| Uuid::parse_str(stem).ok())
+            else {
+                continue;
+            };
+            // The `.bin` carries the last write, each upload is judged once by it
+            if path.extension().is_none_or(|ext| ext != "bin") {
+                continue;
+            }
+            let idle = now
+                .duration_since(entry.metadata().await?.modified()?)
+                .unwrap_or_default();
+            if idle <= ttl || BUSY.lock().map(|busy| busy.contains(&id)).unwrap_or(true) {
+                continue;
+            }
+            fs::remove_file(path_storage(&Self::info_path(id))).await.ok();
+            if fs::remove_file(&path).await.is_ok() {
+                removed += 1;
+            }
+        }
+        Ok(removed)
+    }
+
+    pub async fn discard(&self) -> Result<(), AppError> {
+        match fs::remove_file(path_storage(&Self::info_path(self.id))).await {
+            Ok(()) => {}
+            Err(err) if err.kind() == ErrorKind::NotFound => return Err(not_found()),
+            Err(err) => return Err(err.into()),
+        }
+        fs::remove_file(path_storage(&Self::data_path(self.id))).await.ok();
+        Ok(())
+    }
+}
@@ -3,14 +3,16 @@ use slug::slugify;
 
 /// Max chunk 2 MB
 pub const MAX_CHUNK_SIZE: u64 = 2 * 1024 * 1024;
-/// Max file size 50 MB
-pub const MAX_UPLOAD_SIZE: u64 = 50 * 1024 * 1024;
+/// Max file size 500 MB
+pub const MAX_UPLOAD_SIZE: u64 = 500 * 1024 * 1024;
+/// Max avatar size 5 MB
+pub const MAX_AVATAR_SIZE: u64 = 5 * 1024 * 1024;
 
 /// Ekstensi file yang diizinkan
-const ALLOWED_EXTENSIONS: &[&str] = &["png", "jpg", "jpeg", "gif", "mp4", "pdf"];
+pub const ALLOWED_EXTENSIONS: &[&str] = &["png", "jpg", "jpeg", "gif", "mp4", "pdf", "epub"];
 
 /// MIME type yang diizinkan berdasarkan isi file
-const ALLOWED_MIME_TYPES: &[&str] = &[
+pub const ALLOWED_MIME_TYPES: &[&str] = &[
     "image/jpeg",
     "image/png",
     "image/gif",
@@ -19,28 +21,12 @@ const ALLOWED_MIME_TYPES: &[&str] = &[
     "video/mp4"
 ];
 
-pub fn validate_book_mime(chunk_data: &[u8]) -> Result<(), String> {
-    match infer::get(chunk_data) {
-        Some(kind) => {
-            let mime = kind.mime_type();
-            if ALLOWED_MIME_TYPES.contains(&mime) {
-                Ok(())
-            } else {
-                Err(format!("Unknown mime type: {}", mime))
-            }
-        }
-
-        None => Err(format!("Unknown mime type: {:?}", chunk_data))
-    }
-}
-
-pub fn validate_chunk_size(chunk_data: &[u8], max_size_mb: usize) -> Result<(), String> {
-    let max_bytes = max_size_mb * 1024 * 1024;
-    if chunk_data.len() > max_bytes {
-        Err(format!("File too large (max {} MB)", max_size_mb))
-    } else {
-        Ok(())
-    }
+/// Lowercased extension of `file_name`, if it has one
+pub fn file_extension(file_name: &str) -> Option<String> {
+    Path::new(file_name)
+        .extension()
+        .and_then(|s| s.to_str())
+        .map(str::to_lowercase)
 }
 
 /// Check if a file is safe from attackers
@@ -68,12 +54,6 @@ pub fn sanitize_filename(file_name: &str) -> String {
     }
 }
 
-pub fn timestamped_filename(file_name: &str) -> String {
-    let timestamp = chrono::Utc::now().format("%Y%m%d%H%M%S");
-    let sanitized_name = sanitize_filename(file_name);
-    format!("{}_{}", timestamp, sanitized_name)
-}
-
 /// Check if a path is valid
 pub fn path_is_valid(path: &str) -> bool {
     let path = Path::new(path);
@@ -95,11 +75,3 @@ pub fn path_is_valid(path: &str) -> bool {
     //
     // components.count() == 1
 }
-
-/// Check if upload complete
-fn is_upload_complete(temp_dir: &str, total_chunks: usize) -> bool {
-    match std::fs::read_dir(temp_dir) {
-        Ok(entries) => entries.count() == total_chunks,
-        Err(_) => false,
-    }
-}
@@ -1,10 +1,13 @@
-use anyhow::Context;
-use argon2::password_hash::SaltString;
-use argon2::{Argon2, PasswordHash, PasswordHasher, PasswordVerifier, RECOMMENDED_SALT_LEN};
+use crate::app::hashing::password::PasswordHasher;
+use crate::error::AppError;
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine;
 use rand::rand_core::OsRng;
 use rand::TryRngCore;
 use sha3::digest::DynDigest;
 use sha3::Digest;
+use std::path::Path;
+use tokio::io::AsyncReadExt;
 
 enum FileDriver {
     Sha3_224,
@@ -13,23 +16,6 @@ enum FileDriver {
     Sha3_512,
 }
 
-enum PasswordDriver {
-    Bcrypt,
-    Argon2i,
-    Argon2id,
-}
-
-impl PasswordDriver {
-    fn from_env() -> Self {
-        match std::env::var("HASH_DRIVER").as_deref() {
-            Ok("bcrypt") => Self::Bcrypt,
-            Ok("argon2i") => Self::Argon2i,
-            Ok("argon2id") => Self::Argon2id,
-            _ => Self::Bcrypt,
-        }
-    }
-}
-
 impl FileDriver {
     fn from_env() -> Self {
         match std::env::var("FILE_DRIVER").as_deref() {
@@ -40,78 +26,185 @@ impl FileDriver {
             _ => Self::Sha3_256,
         }
     }
+
+    fn name(&self) -> &'static str {
+        match self {
+            Self::Sha3_224 => "sha3-224",
+            Self::Sha3_256 => "sha3-256",
+            Self::Sha3_384 => "sha3-384",
+            Self::Sha3_512 => "sha3-512",
+        }
+    }
+}
+
+/// Name of the digest files are hashed with, as clients spell it in checksum headers
+pub fn file_hash_algorithm() -> &'static str {
+    FileDriver::from_env().name()
 }
 
 pub fn hash_password(password: &str) -> anyhow::Result<String> {
-    match PasswordDriver::from_env() {
-        PasswordDriver::Bcrypt => {
-            let cost = std::env::var("BRCYPT_COST")
-                .unwrap_or_else(|_| "12".into())
-                .parse()?;
+    PasswordHasher::from_env()?.hash(password)
+}
+
+/// Check whether a stored hash was made with another driver or other parameters
+/// than the ones currently configured, so it should be replaced after login
+pub fn needs_rehash(hashed_password: &str) -> anyhow::Result<bool> {
+    Ok(PasswordHasher::from_env()?.needs_rehash(hashed_password))
+}
+
+pub fn verify_password(hashed_password: &str, password: &str) -> anyhow::Result<bool> {
+    PasswordHasher::from_env()?.verify(hashed_password, password)
+}
+
+/// Hashes files with the `FILE_DRIVER` digest, fed as the data arrives
+pub struct FileHasher(Box<dyn DynDigest + Send>);
+
+impl FileHasher {
+    pub fn new() -> Self {
+        Self(match FileDriver::from_env() {
+            FileDriver::Sha3_224 => Box::new(sha3::Sha3_224::new()),
+            FileDriver::Sha3_256 => Box::new(sha3::Sha3_256::new()),
+            FileDriver::Sha3_384 => Box::new(sha3::Sha3_384::new()),
+            FileDriver::Sha3_512 => Box::new(sha3::Sha3_512::new()),
+        })
+    }
+
+    pub fn update(&mut self, data: &[u8]) {
+        self.0.update(data);
+    }
+
+    /// Hex digest
+    pub fn finalize(mut self) -> String {
+        hex::encode(self.0.finalize_reset())
+    }
+}
+
+impl Default for FileHasher {
+    fn default() -> Self {
+        Self::new()
+    }
+}
 
-            Ok(bcrypt::hash(password, cost)?)
+/// `<algorithm> <base64 digest>` as in tus' `Upload-Checksum`, the algorithm
+/// has to be the one `FileHasher` uses
+pub struct Checksum {
+    expected: String,
+}
+
+impl Checksum {
+    pub fn parse(header: &str) -> Result<Self, AppError> {
+        let (algorithm, digest) = header
+            .split_once(' ')
+            .ok_or_else(|| AppError::bad_request("Checksum must be '<algorithm> <digest>'"))?;
+
+        if !algorithm.eq_ignore_ascii_case(file_hash_algorithm()) {
+            return Err(AppError::bad_request(format!(
+                "Unsupported checksum algorithm, use {}",
+                file_hash_algorithm()
+            )));
         }
-        PasswordDriver::Argon2i | PasswordDriver::Argon2id => {
-            let mem: u32 = std::env::var("ARGON2_MEMORY")
-                .unwrap_or_else(|_| "4096".into())
-                .parse()?;
-
-            let time: u32 = std::env::var("ARGON2_TIME")
-                .unwrap_or_else(|_| "3".into())
-                .parse()?;
-            let thread: u32 = std::env::var("ARGON2_THREADS")
-                .unwrap_or_else(|_| "1".into())
-                .parse()?;
-
-            let params =
-                argon2::Params::new(mem, time, thread, None).expect("Invalid Argon2 parameters");
-            let mut random_salt = OsRng;
-
-            let mut bytes = [0u8; RECOMMENDED_SALT_LEN];
-            random_salt.try_fill_bytes(&mut bytes)?;
-            let hasher = Argon2::new(argon2::Algorithm::Argon2id, argon2::Version::V0x13, params);
-            let salt = SaltString::encode_b64(&mut bytes)?;
-            let hash = hasher
-                .hash_password(password.as_bytes(), &salt)?
-                .to_string();
-            Ok(hash)
+        let expected = STANDARD
+            .decode(digest.trim())
+            .map_err(|_| AppError::bad_request("Checksum digest is not base64"))?;
+
+        Ok(Self {
+            expected: hex::encode(expected),
+        })
+    }
+
+    /// `actual` is the hex digest of the received bytes
+    pub fn verify(&self, actual: &str) -> Result<(), AppError> {
+        if actual != self.expected {
+            return Err(AppError::ChecksumMismatch("Checksum mismatch".to_string()));
         }
+        Ok(())
     }
 }
 
-pub fn verify_password(hashed_password: &str, password: &str) -> anyhow::Result<bool> {
-    let verified = if hashed_password.starts_with("$2b$") {
-        bcrypt::verify(password, hashed_password).context("Failed")?
-    } else {
-        let parsed = PasswordHash::new(&hashed_password)?;
-        Argon2::default()
-            .verify_password(password.as_bytes(), &parsed)
-            .is_ok()
-    };
-    Ok(verified)
+/// Digest of a file on disk, read a block at a time
+pub async fn hash_file_at(path: &Path) -> anyhow::Result<String> {
+    let mut file = tokio::fs::File::open(path).await?;
+    let mut hasher = FileHasher::new();
+    let mut buffer = vec![0u8; 64 * 1024];
+    loop {
+        let read = file.read(&mut buffer).await?;
+        if read == 0 {
+            break;
+        }
+        hasher.update(&buffer[..read]);
+    }
+    Ok(hasher.finalize())
 }
 
-pub fn hash_file(data: &[u8]) -> anyhow::Result<String> {
-    let mut hasher: Box<dyn DynDigest> = match FileDriver::from_env() {
-        FileDriver::Sha3_224 => Box::new(sha3::Sha3_224::new()),
-        FileDriver::Sha3_256 => Box::new(sha3::Sha3_256::new()),
-        FileDriver::Sha3_384 => Box::new(sha3::Sha3_384::new()),
-        FileDriver::Sha3_512 => Box::new(sha3::Sha3_512::new()),
-    };
-
-    hasher.update(data);
-    let result = hasher.finalize_reset();
-    Ok(hex::encode(result))
+/// Generate a random token of `len` bytes, hex encoded
+pub fn generate_token(len: usize) -> anyhow::Result<String> {
+    let mut bytes = vec![0u8; len];
+    OsRng.try_fill_bytes(&mut bytes)?;
+    Ok(hex::encode(bytes))
 }
 
-pub fn verify_file(expected_hex: &str, data: &[u8]) -> anyhow::Result<bool> {
-    let computed = hash_file(data)?;
-    Ok(computed.eq_ignore_ascii_case(expected_hex))
+/// Hash a high-entropy token (refresh tokens, one-time codes) for lookup at rest
+pub fn hash_token(token: &str) -> String {
+    hex::encode(sha3::Sha3_256::digest(token.as_bytes()))
 }
 
-pub async fn verify_hash_file(file_path: &str) -> anyhow::Result<bool> {
-    let hash_path = format!("{}.hash", file_path);
-    let expected_hash = tokio::fs::read_to_string(&hash_path).await?.trim().to_string();
-    let file_bytes = tokio::fs::read(file_path).await?;
-    verify_file(&expected_hash, &file_bytes)
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::http::StatusCode;
+
+    fn digest_of(data: &[u8]) -> String {
+        let mut hasher = FileHasher::new();
+        hasher.update(data);
+        hasher.finalize()
+    }
+
+    fn header_for(algorithm: &str, digest: &str) -> String {
+        format!("{} {}", algorithm, STANDARD.encode(hex::decode(digest).unwrap()))
+    }
+
+    #[test]
+    fn matching_checksum_verifies() {
+        let digest = digest_of(b"hello tus");
+        let checksum = Checksum::parse(&header_for(file_hash_algorithm(), &digest)).unwrap();
+
+        assert!(checksum.verify(&digest).is_ok());
+    }
+
+    #[test]
+    fn algorithm_is_case_insensitive() {
+        let digest = digest_of(b"hello tus");
+        let header = header_for(&file_hash_algorithm().to_uppercase(), &digest);
+
+        assert!(Checksum::parse(&header).unwrap().verify(&digest).is_ok());
+    }
+
+    #[test]
+    fn other_algorithm_is_refused() {
+        let header = header_for("sha1", &hex::encode([7u8; 20]));
+        let err = Checksum::parse(&header).err().unwrap();
+
+        assert_eq!(err.status(), StatusCode::BAD_REQUEST);
+    }
+
+    #[test]
+    fn missing_digest_is_refused() {
+        let err = Checksum::parse(file_hash_algorithm()).err().unwrap();
+        assert_eq!(err.status(), StatusCode::BAD_REQUEST);
+    }
+
+    #[test]
+    fn digest_must_be_base64() {
+        let err = Checksum::parse(&format!("{} not*base64!", file_hash_algorithm())).err().unwrap();
+        assert_eq!(err.status(), StatusCode::BAD_REQUEST);
+    }
+
+    #[test]
+    fn mismatch_is_460() {
+        let checksum = Checksum::parse(&header_for(file_hash_algorithm(), &digest_of(b"sent"))).unwrap();
+        let err = checksum.verify(&digest_of(b"received")).unwrap_err();
+
+        assert!(matches!(err, AppError::ChecksumMismatch(_)));
+        assert_eq!(err.status().as_u16(), 460);
+    }
 }
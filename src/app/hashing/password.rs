@@ -0,0 +1,316 @@
+use anyhow::{anyhow, bail, Context};
+use argon2::password_hash::SaltString;
+use argon2::{Argon2, PasswordHash, PasswordHasher as _, RECOMMENDED_SALT_LEN};
+use rand::rand_core::OsRng;
+use rand::TryRngCore;
+
+/// Algorithm used for new password hashes, picked with `HASH_DRIVER`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PasswordDriver {
+    Bcrypt,
+    Argon2i,
+    Argon2id,
+}
+
+impl PasswordDriver {
+    pub fn from_env() -> Self {
+        match std::env::var("HASH_DRIVER").as_deref() {
+            Ok("bcrypt") => Self::Bcrypt,
+            Ok("argon2i") => Self::Argon2i,
+            Ok("argon2id") => Self::Argon2id,
+            _ => Self::Bcrypt,
+        }
+    }
+
+    fn argon2_algorithm(&self) -> Option<argon2::Algorithm> {
+        match self {
+            Self::Bcrypt => None,
+            Self::Argon2i => Some(argon2::Algorithm::Argon2i),
+            Self::Argon2id => Some(argon2::Algorithm::Argon2id),
+        }
+    }
+}
+
+/// What an encoded hash says about how it was made
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum HashScheme {
+    /// `$2a$`, `$2b$`, `$2x$` or `$2y$` (PHP, OpenBSD, node, ...)
+    Bcrypt { cost: u32 },
+    /// PHC string `$argon2{i,d,id}$v=..$m=..,t=..,p=..$salt$hash`
+    Argon2 {
+        algorithm: argon2::Algorithm,
+        version: argon2::Version,
+        params: argon2::Params,
+    },
+}
+
+impl HashScheme {
+    /// Detect the algorithm from the encoded hash
+    pub fn detect(hashed_password: &str) -> anyhow::Result<Self> {
+        if let Some(rest) = ["$2a$", "$2b$", "$2x$", "$2y$"]
+            .iter()
+            .find_map(|prefix| hashed_password.strip_prefix(prefix))
+        {
+            let cost = rest
+                .split('$')
+                .next()
+                .and_then(|cost| cost.parse().ok())
+                .context("Malformed bcrypt hash")?;
+            return Ok(Self::Bcrypt { cost });
+        }
+
+        if hashed_password.starts_with("$argon2") {
+            let parsed = PasswordHash::new(hashed_password).map_err(|e| anyhow!(e))?;
+            let algorithm = argon2::Algorithm::try_from(parsed.algorithm).map_err(|e| anyhow!(e))?;
+            // Hashes without `v=` predate version 0x13
+            let version = match parsed.version {
+                Some(version) => argon2::Version::try_from(version).map_err(|e| anyhow!(e))?,
+                None => argon2::Version::V0x10,
+            };
+            let params = argon2::Params::try_from(&parsed).map_err(|e| anyhow!(e))?;
+            return Ok(Self::Argon2 { algorithm, version, params });
+        }
+
+        bail!("Unknown password hash format")
+    }
+}
+
+/// Hashes and verifies passwords with the configured driver and cost settings
+#[derive(Debug, Clone)]
+pub struct PasswordHasher {
+    driver: PasswordDriver,
+    bcrypt_cost: u32,
+    argon2_params: argon2::Params,
+}
+
+impl PasswordHasher {
+    pub fn new(driver: PasswordDriver, bcrypt_cost: u32, argon2_params: argon2::Params) -> Self {
+        Self { driver, bcrypt_cost, argon2_params }
+    }
+
+    /// Read `HASH_DRIVER`, `BCRYPT_COST`, `ARGON2_MEMORY`, `ARGON2_TIME` and `ARGON2_THREADS`
+    pub fn from_env() -> anyhow::Result<Self> {
+        // `BRCYPT_COST` is the old, misspelled name
+        let bcrypt_cost = std::env::var("BCRYPT_COST")
+            .or_else(|_| std::env::var("BRCYPT_COST"))
+            .unwrap_or_else(|_| "12".into())
+            .parse()?;
+
+        let mem: u32 = std::env::var("ARGON2_MEMORY")
+            .unwrap_or_else(|_| "4096".into())
+            .parse()?;
+        let time: u32 = std::env::var("ARGON2_TIME")
+            .unwrap_or_else(|_| "3".into())
+            .parse()?;
+        let thread: u32 = std::env::var("ARGON2_THREADS")
+            .unwrap_or_else(|_| "1".into())
+            .parse()?;
+        let argon2_params = argon2::Params::new(mem, time, thread, None)
+            .map_err(|e| anyhow!("Invalid Argon2 parameters: {}", e))?;
+
+        Ok(Self::new(PasswordDriver::from_env(), bcrypt_cost, argon2_params))
+    }
+
+    pub fn hash(&self, password: &str) -> anyhow::Result<String> {
+        let Some(algorithm) = self.driver.argon2_algorithm() else {
+            return Ok(bcrypt::hash(password, self.bcrypt_cost)?);
+        };
+
+        let mut bytes = [0u8; RECOMMENDED_SALT_LEN];
+        OsRng.try_fill_bytes(&mut bytes)?;
+        let salt = SaltString::encode_b64(&bytes).map_err(|e| anyhow!(e))?;
+
+        let hasher = Argon2::new(algorithm, argon2::Version::V0x13, self.argon2_params.clone());
+        let hash = hasher
+            .hash_password(password.as_bytes(), &salt)
+            .map_err(|e| anyhow!(e))?
+            .to_string();
+        Ok(hash)
+    }
+
+    /// Verify against any supported hash, whatever driver is configured now
+    pub fn verify(&self, hashed_password: &str, password: &str) -> anyhow::Result<bool> {
+        match HashScheme::detect(hashed_password)? {
+            HashScheme::Bcrypt { .. } => Ok(bcrypt::verify(password, hashed_password)?),
+            HashScheme::Argon2 { algorithm, version, params } => {
+                let parsed = PasswordHash::new(hashed_password).map_err(|e| anyhow!(e))?;
+                let salt = parsed.salt.context("Argon2 hash without salt")?;
+
+                // Recompute with the hash's own version, `verify_password` would
+                // assume 0x13 for legacy hashes that carry no `v=` field
+                let computed = Argon2::new(algorithm, version, params.clone())
+                    .hash_password_customized(
+                        password.as_bytes(),
+                        Some(algorithm.ident()),
+                        Some(version.into()),
+                        params,
+                        salt,
+                    )
+                    .map_err(|e| anyhow!(e))?;
+                Ok(computed.hash == parsed.hash)
+            }
+        }
+    }
+
+    /// True when the hash wasn't made with the current driver and parameters
+    pub fn needs_rehash(&self, hashed_password: &str) -> bool {
+        let Ok(scheme) = HashScheme::detect(hashed_password) else {
+            return true;
+        };
+
+        match (self.driver.argon2_algorithm(), scheme) {
+            (None, HashScheme::Bcrypt { cost }) => cost != self.bcrypt_cost,
+            (Some(expected), HashScheme::Argon2 { algorithm, version, params }) => {
+                algorithm != expected
+                    || version != argon2::Version::V0x13
+                    || params.m_cost() != self.argon2_params.m_cost()
+                    || params.t_cost() != self.argon2_params.t_cost()
+                    || params.p_cost() != self.argon2_params.p_cost()
+            }
+            _ => true,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn hasher(driver: PasswordDriver) -> PasswordHasher {
+        // Low cost keeps the tests fast
+        PasswordHasher::new(driver, 4, argon2::Params::new(1024, 1, 1, None).unwrap())
+    }
+
+    #[test]
+    fn bcrypt_round_trip() {
+        let hasher = hasher(PasswordDriver::Bcrypt);
+        let hash = hasher.hash("secret").unwrap();
+
+        assert!(hash.starts_with("$2b$04$"));
+        assert!(hasher.verify(&hash, "secret").unwrap());
+        assert!(!hasher.verify(&hash, "wrong").unwrap());
+    }
+
+    #[test]
+    fn argon2i_round_trip() {
+        let hasher = hasher(PasswordDriver::Argon2i);
+        let hash = hasher.hash("secret").unwrap();
+
+        assert!(hash.starts_with("$argon2i$v=19$m=1024,t=1,p=1$"));
+        assert!(hasher.verify(&hash, "secret").unwrap());
+        assert!(!hasher.verify(&hash, "wrong").unwrap());
+    }
+
+    #[test]
+    fn argon2id_round_trip() {
+        let hasher = hasher(PasswordDriver::Argon2id);
+        let hash = hasher.hash("secret").unwrap();
+
+        assert!(hash.starts_with("$argon2id$v=19$m=1024,t=1,p=1$"));
+        assert!(hasher.verify(&hash, "secret").unwrap());
+        assert!(!hasher.verify(&hash, "wrong").unwrap());
+    }
+
+    #[test]
+    fn verifies_hashes_from_other_drivers() {
+        let bcrypt = hasher(PasswordDriver::Bcrypt).hash("secret").unwrap();
+        let argon2i = hasher(PasswordDriver::Argon2i).hash("secret").unwrap();
+        let argon2id = hasher(PasswordDriver::Argon2id).hash("secret").unwrap();
+
+        for driver in [PasswordDriver::Bcrypt, PasswordDriver::Argon2i, PasswordDriver::Argon2id] {
+            let hasher = hasher(driver);
+            assert!(hasher.verify(&bcrypt, "secret").unwrap());
+            assert!(hasher.verify(&argon2i, "secret").unwrap());
+            assert!(hasher.verify(&argon2id, "secret").unwrap());
+        }
+    }
+
+    #[test]
+    fn verifies_imported_bcrypt_hashes() {
+        let hasher = hasher(PasswordDriver::Argon2id);
+
+        // PHP password_hash()
+        let php = "$2y$10$.vGA1O9wmRjrwAVXD98HNOgsNpDczlqm3Jq7KnEd1rVAGv3Fykk1a";
+        assert!(hasher.verify(php, "rasmuslerdorf").unwrap());
+        assert!(!hasher.verify(php, "rasmus").unwrap());
+
+        // OpenBSD / crypt_blowfish test vector
+        let openbsd = "$2a$05$CCCCCCCCCCCCCCCCCCCCC.E5YPO9kmyuRGyh0XouQYb4YMJKvyOeW";
+        assert!(hasher.verify(openbsd, "U*U").unwrap());
+    }
+
+    #[test]
+    fn verifies_imported_argon2_hashes() {
+        let hasher = hasher(PasswordDriver::Bcrypt);
+
+        // Reference implementation vectors, password "password" and salt "somesalt"
+        let argon2i = "$argon2i$v=19$m=65536,t=2,p=1$c29tZXNhbHQ$wWKIMhR9lyDFvRz9YTZweHKfbftvj+qf+YFY4NeBbtA";
+        assert!(hasher.verify(argon2i, "password").unwrap());
+        assert!(!hasher.verify(argon2i, "passwort").unwrap());
+
+        let argon2id = "$argon2id$v=19$m=65536,t=2,p=1$c29tZXNhbHQ$CTFhFdXPJO1aFaMaO6Mm5c8y7cJHAph8ArZWb2GRPPc";
+        assert!(hasher.verify(argon2id, "password").unwrap());
+
+        // Version 0x10 hashes carry no `v=` field
+        let legacy = "$argon2i$m=65536,t=2,p=1$c29tZXNhbHQ$9sTbSlTio3Biev89thdrlKKiCaYsjjYVJxGAL3swxpQ";
+        assert!(hasher.verify(legacy, "password").unwrap());
+    }
+
+    #[test]
+    fn detects_scheme() {
+        assert_eq!(
+            HashScheme::detect("$2y$10$.vGA1O9wmRjrwAVXD98HNOgsNpDczlqm3Jq7KnEd1rVAGv3Fykk1a").unwrap(),
+            HashScheme::Bcrypt { cost: 10 }
+        );
+
+        let hash = hasher(PasswordDriver::Argon2i).hash("secret").unwrap();
+        match HashScheme::detect(&hash).unwrap() {
+            HashScheme::Argon2 { algorithm, version, params } => {
+                assert_eq!(algorithm, argon2::Algorithm::Argon2i);
+                assert_eq!(version, argon2::Version::V0x13);
+                assert_eq!(params.m_cost(), 1024);
+            }
+            other => panic!("unexpected scheme {:?}", other),
+        }
+    }
+
+    #[test]
+    fn rejects_unknown_formats() {
+        let hasher = hasher(PasswordDriver::Bcrypt);
+
+        assert!(HashScheme::detect("plaintext").is_err());
+        assert!(HashScheme::detect("$1$abc$def").is_err());
+        assert!(hasher.verify("$2b$xx$garbage", "secret").is_err());
+    }
+
+    #[test]
+    fn needs_rehash_on_driver_or_cost_change() {
+        let bcrypt = hasher(PasswordDriver::Bcrypt);
+        let argon2i = hasher(PasswordDriver::Argon2i);
+        let argon2id = hasher(PasswordDriver::Argon2id);
+
+        let bcrypt_hash = bcrypt.hash("secret").unwrap();
+        let argon2i_hash = argon2i.hash("secret").unwrap();
+        let argon2id_hash = argon2id.hash("secret").unwrap();
+
+        assert!(!bcrypt.needs_rehash(&bcrypt_hash));
+        assert!(!argon2i.needs_rehash(&argon2i_hash));
+        assert!(!argon2id.needs_rehash(&argon2id_hash));
+
+        assert!(bcrypt.needs_rehash(&argon2id_hash));
+        assert!(argon2id.needs_rehash(&bcrypt_hash));
+        assert!(argon2id.needs_rehash(&argon2i_hash));
+        assert!(argon2i.needs_rehash(&argon2id_hash));
+
+        let stronger_bcrypt = PasswordHasher::new(PasswordDriver::Bcrypt, 5, argon2::Params::default());
+        assert!(stronger_bcrypt.needs_rehash(&bcrypt_hash));
+
+        let stronger_argon2 = PasswordHasher::new(
+            PasswordDriver::Argon2id,
+            4,
+            argon2::Params::new(2048, 1, 1, None).unwrap(),
+        );
+        assert!(stronger_argon2.needs_rehash(&argon2id_hash));
+        assert!(argon2id.needs_rehash("not a hash"));
+    }
+}
@@ -0,0 +1,2 @@
+pub mod panic;
+pub mod tus;
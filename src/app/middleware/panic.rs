@@ -0,0 +1,60 @@
+use axum::body::Body;
+use axum::extract::Request;
+use axum::http::StatusCode;
+use axum::middleware::Next;
+use axum::response::{IntoResponse, Response};
+use axum::Json;
+use serde_json::json;
+use std::any::Any;
+use tracing::error;
+
+const REQUEST_ID_HEADER: &str = "x-request-id";
+
+/// Marks the response `CatchPanicLayer` built for a panicked handler
+#[derive(Clone, Copy)]
+struct Panicked;
+
+/// Handler for `CatchPanicLayer::custom`, logs the panic and answers with a plain 500
+pub fn handle_panic(err: Box<dyn Any + Send + 'static>) -> Response<Body> {
+    let detail = if let Some(msg) = err.downcast_ref::<String>() {
+        msg.as_str()
+    } else if let Some(msg) = err.downcast_ref::<&str>() {
+        msg
+    } else {
+        "unknown panic"
+    };
+    error!("Handler panicked: {}", detail);
+
+    let mut response = StatusCode::INTERNAL_SERVER_ERROR.into_response();
+    response.extensions_mut().insert(Panicked);
+    response
+}
+
+/// Sits outside `CatchPanicLayer` and turns its response into the usual JSON
+/// error body, with the request id so the failure can be found in the logs.
+/// `PropagateRequestIdLayer` copies the id into the response headers.
+pub async fn panic_response(request: Request, next: Next) -> Response {
+    let request_id = request
+        .headers()
+        .get(REQUEST_ID_HEADER)
+        .and_then(|value| value.to_str().ok())
+        .map(str::to_string);
+
+    let response = next.run(request).await;
+    if response.extensions().get::<Panicked>().is_none() {
+        return response;
+    }
+
+    if let Some(id) = &request_id {
+        error!("Request {} failed with a panic", id);
+    }
+    (
+        StatusCode::INTERNAL_SERVER_ERROR,
+        Json(json!({
+            "error": "internal_error",
+            "message": "Internal server error",
+            "request_id": request_id
+        })),
+    )
+        .into_response()
+}
@@ -0,0 +1,36 @@
+use crate::app::files::tus::TUS_VERSION;
+use axum::extract::Request;
+use axum::http::{HeaderValue, Method, StatusCode};
+use axum::middleware::Next;
+use axum::response::{IntoResponse, Response};
+use axum::Json;
+use serde_json::json;
+
+/// Rejects requests that speak another tus version with 412, as the protocol
+/// asks, and marks every response with `Tus-Resumable`. OPTIONS is how
+/// clients discover the version, so it is let through.
+pub async fn tus_resumable(request: Request, next: Next) -> Response {
+    let version = request
+        .headers()
+        .get("tus-resumable")
+        .and_then(|value| value.to_str().ok());
+
+    let mut response = if request.method() != Method::OPTIONS && version != Some(TUS_VERSION) {
+        (
+            StatusCode::PRECONDITION_FAILED,
+            [("tus-version", TUS_VERSION)],
+            Json(json!({
+                "error": "unsupported_tus_version",
+                "message": format!("Tus-Resumable must be {}", TUS_VERSION),
+            })),
+        )
+            .into_response()
+    } else {
+        next.run(request).await
+    };
+
+    response
+        .headers_mut()
+        .insert("tus-resumable", HeaderValue::from_static(TUS_VERSION));
+    response
+}
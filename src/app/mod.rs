@@ -1,2 +1,5 @@
+pub mod auth;
 pub mod files;
 pub mod hashing;
+pub mod middleware;
+pub mod notify;
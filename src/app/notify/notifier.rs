@@ -0,0 +1,73 @@
+use crate::app::files::files::path_storage;
+use entity::user;
+use futures::future::BoxFuture;
+use std::path::PathBuf;
+use std::sync::Arc;
+use tracing::info;
+
+/// Delivers account messages (password reset tokens, ...) to a user
+pub trait Notifier: Send + Sync {
+    fn send_password_reset<'a>(
+        &'a self,
+        user: &'a user::Model,
+        token: &'a str,
+    ) -> BoxFuture<'a, anyhow::Result<()>>;
+}
+
+/// Writes the message to the application log, for local development
+pub struct LogNotifier;
+
+impl Notifier for LogNotifier {
+    fn send_password_reset<'a>(
+        &'a self,
+        user: &'a user::Model,
+        token: &'a str,
+    ) -> BoxFuture<'a, anyhow::Result<()>> {
+        Box::pin(async move {
+            info!("Password reset token for {}: {}", user.username, token);
+            Ok(())
+        })
+    }
+}
+
+/// Drops every message as a text file into a directory (an outbox)
+pub struct FileNotifier {
+    dir: PathBuf,
+}
+
+impl FileNotifier {
+    pub fn new(dir: PathBuf) -> Self {
+        Self { dir }
+    }
+}
+
+impl Notifier for FileNotifier {
+    fn send_password_reset<'a>(
+        &'a self,
+        user: &'a user::Model,
+        token: &'a str,
+    ) -> BoxFuture<'a, anyhow::Result<()>> {
+        Box::pin(async move {
+            tokio::fs::create_dir_all(&self.dir).await?;
+
+            let timestamp = chrono::Utc::now().format("%Y-%m-%d_%H-%M-%S");
+            let path = self.dir.join(format!("{}_{}_password_reset.txt", timestamp, user.id));
+            let body = format!(
+                "To: {}\nSubject: Password reset\n\nUse this token to reset your password: {}\n",
+                user.username, token
+            );
+            tokio::fs::write(&path, body).await?;
+
+            info!("Password reset message written to {:?}", path);
+            Ok(())
+        })
+    }
+}
+
+/// Pick the notifier from `NOTIFIER` (`log` or `file`)
+pub fn notifier_from_env() -> Arc<dyn Notifier> {
+    match std::env::var("NOTIFIER").as_deref() {
+        Ok("file") => Arc::new(FileNotifier::new(path_storage("outbox"))),
+        _ => Arc::new(LogNotifier),
+    }
+}
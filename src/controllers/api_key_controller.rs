@@ -0,0 +1,106 @@
+use crate::app::auth::api_key::{create_api_key, Scope};
+use crate::app::auth::extractor::AuthUser;
+use crate::dto::PublicApiKey;
+use crate::error::AppError;
+use crate::respons::{api_response, api_response_single};
+use crate::utils::AppState;
+use crate::validation::{Validate, ValidatedJson, Validator};
+use axum::extract::{Path, State};
+use axum::http::StatusCode;
+use axum::response::IntoResponse;
+use chrono::{Duration, Utc};
+use entity::api_key;
+use sea_orm::{ColumnTrait, EntityTrait, QueryFilter, QueryOrder};
+use serde::Deserialize;
+use serde_json::json;
+use std::sync::Arc;
+use uuid::Uuid;
+
+#[derive(Deserialize)]
+pub struct ApiKeyReq {
+    name: String,
+    scopes: Vec<Scope>,
+    /// Leave out for a key that never expires
+    expires_in_days: Option<i64>,
+}
+
+impl Validate for ApiKeyReq {
+    fn validate(&self) -> Result<(), AppError> {
+        let mut v = Validator::new();
+        v.length("name", &self.name, 1, 100)
+            .range("scopes", self.scopes.len(), 1, 10);
+        if let Some(days) = self.expires_in_days {
+            v.range("expires_in_days", days, 1, 3650);
+        }
+        v.finish()
+    }
+}
+
+/// Keys can only be managed from a logged in session, so a leaked key can't mint new ones
+fn ensure_session(auth: &AuthUser) -> Result<(), AppError> {
+    auth.require_session("manage API keys")
+}
+
+#[axum::debug_handler]
+pub async fn list_api_keys(
+    state: State<Arc<AppState>>,
+    auth: AuthUser,
+) -> Result<impl IntoResponse, AppError> {
+    ensure_session(&auth)?;
+
+    let keys = api_key::Entity::find()
+        .filter(api_key::Column::UserId.eq(auth.id))
+        .order_by_desc(api_key::Column::CreatedAt)
+        .all(&state.database_connection)
+        .await?
+        .into_iter()
+        .map(PublicApiKey::from)
+        .collect::<Vec<_>>();
+
+    api_response(keys)
+}
+
+#[axum::debug_handler]
+pub async fn create_key(
+    state: State<Arc<AppState>>,
+    auth: AuthUser,
+    ValidatedJson(form): ValidatedJson<ApiKeyReq>,
+) -> Result<impl IntoResponse, AppError> {
+    ensure_session(&auth)?;
+
+    let expires_at = form.expires_in_days.map(|days| Utc::now() + Duration::days(days));
+
+    let (key_model, key) = create_api_key(
+        &state.database_connection,
+        auth.id,
+        &form.name,
+        &form.scopes,
+        expires_at,
+    )
+    .await?;
+
+    Ok((StatusCode::CREATED, api_response_single(json!({
+        "api_key": PublicApiKey::from(key_model),
+        "key": key
+    }))?))
+}
+
+#[axum::debug_handler]
+pub async fn delete_api_key(
+    state: State<Arc<AppState>>,
+    auth: AuthUser,
+    Path(id): Path<Uuid>,
+) -> Result<impl IntoResponse, AppError> {
+    ensure_session(&auth)?;
+
+    let deleted = api_key::Entity::delete_many()
+        .filter(api_key::Column::Id.eq(id))
+        .filter(api_key::Column::UserId.eq(auth.id))
+        .exec(&state.database_connection)
+        .await?;
+    if deleted.rows_affected == 0 {
+        return Err(AppError::not_found("API key not found"));
+    }
+
+    Ok((StatusCode::OK, api_response_single(json!({ "message": "API key revoked" }))?))
+}
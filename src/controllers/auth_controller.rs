@@ -0,0 +1,323 @@
+use crate::app::auth::extractor::AuthUser;
+use crate::app::auth::jwt::{decode_token, TokenKind, TokenPair};
+use crate::app::auth::password_reset::{redeem_password_reset, request_password_reset};
+use crate::app::auth::session::{end_session, rotate_session, start_session};
+use crate::app::auth::throttle::{client_ip, LoginThrottle};
+use crate::app::auth::two_factor::{
+    begin_enrollment, confirm_enrollment, disable, verify_second_factor, SecondFactor,
+};
+use crate::dto::PublicUser;
+use crate::error::AppError;
+use crate::respons::api_response_single;
+use crate::utils::AppState;
+use crate::validation::{Validate, ValidatedJson, Validator};
+use axum::extract::{ConnectInfo, State};
+use axum::http::{HeaderMap, StatusCode};
+use axum::response::IntoResponse;
+use entity::user;
+use sea_orm::{ColumnTrait, DatabaseConnection, EntityTrait, QueryFilter};
+use serde::Deserialize;
+use serde_json::{json, Value};
+use std::future::Future;
+use std::net::SocketAddr;
+use std::sync::Arc;
+
+#[derive(Deserialize)]
+pub struct RefreshReq {
+    refresh_token: String,
+}
+
+impl Validate for RefreshReq {
+    fn validate(&self) -> Result<(), AppError> {
+        Validator::new()
+            .length("refresh_token", &self.refresh_token, 1, 4096)
+            .finish()
+    }
+}
+
+#[derive(Deserialize)]
+pub struct LogoutReq {
+    refresh_token: String,
+    #[serde(default)]
+    all_sessions: bool,
+}
+
+impl Validate for LogoutReq {
+    fn validate(&self) -> Result<(), AppError> {
+        Validator::new()
+            .length("refresh_token", &self.refresh_token, 1, 4096)
+            .finish()
+    }
+}
+
+#[derive(Deserialize)]
+pub struct ForgotPasswordReq {
+    username: String,
+}
+
+impl Validate for ForgotPasswordReq {
+    fn validate(&self) -> Result<(), AppError> {
+        Validator::new()
+            .length("username", &self.username, 1, 32)
+            .finish()
+    }
+}
+
+#[derive(Deserialize)]
+pub struct ResetPasswordReq {
+    token: String,
+    new_password: String,
+}
+
+impl Validate for ResetPasswordReq {
+    fn validate(&self) -> Result<(), AppError> {
+        Validator::new()
+            .length("token", &self.token, 1, 256)
+            .length("new_password", &self.new_password, 8, 128)
+            .finish()
+    }
+}
+
+#[derive(Deserialize)]
+pub struct TotpCodeReq {
+    code: String,
+}
+
+impl Validate for TotpCodeReq {
+    fn validate(&self) -> Result<(), AppError> {
+        Validator::new().length("code", &self.code, 1, 16).finish()
+    }
+}
+
+/// One of `code` and `recovery_code`, both of sane length
+fn validate_second_factor(v: &mut Validator, code: Option<&str>, recovery_code: Option<&str>) {
+    if code.is_none() && recovery_code.is_none() {
+        v.check("code", false, "or recovery_code is required");
+    }
+    if let Some(code) = code {
+        v.length("code", code, 1, 16);
+    }
+    if let Some(recovery_code) = recovery_code {
+        v.length("recovery_code", recovery_code, 1, 64);
+    }
+}
+
+#[derive(Deserialize)]
+pub struct SecondFactorReq {
+    code: Option<String>,
+    recovery_code: Option<String>,
+}
+
+impl Validate for SecondFactorReq {
+    fn validate(&self) -> Result<(), AppError> {
+        let mut v = Validator::new();
+        validate_second_factor(&mut v, self.code.as_deref(), self.recovery_code.as_deref());
+        v.finish()
+    }
+}
+
+#[derive(Deserialize)]
+pub struct MfaVerifyReq {
+    mfa_token: String,
+    code: Option<String>,
+    recovery_code: Option<String>,
+}
+
+impl Validate for MfaVerifyReq {
+    fn validate(&self) -> Result<(), AppError> {
+        let mut v = Validator::new();
+        v.length("mfa_token", &self.mfa_token, 1, 4096);
+        validate_second_factor(&mut v, self.code.as_deref(), self.recovery_code.as_deref());
+        v.finish()
+    }
+}
+
+/// Body returned once a login is complete
+pub fn login_payload(user_model: user::Model, tokens: TokenPair) -> Value {
+    json!({
+        "user": PublicUser::from(user_model),
+        "access_token": tokens.access_token,
+        "refresh_token": tokens.refresh_token,
+        "token_type": tokens.token_type,
+        "expires_in": tokens.expires_in
+    })
+}
+
+async fn find_user(db: &DatabaseConnection, auth: &AuthUser) -> Result<user::Model, AppError> {
+    user::Entity::find_by_id(auth.id)
+        .one(db)
+        .await?
+        .ok_or_else(|| AppError::not_found("User not found"))
+}
+
+#[axum::debug_handler]
+pub async fn refresh(
+    state: State<Arc<AppState>>,
+    ValidatedJson(form): ValidatedJson<RefreshReq>,
+) -> Result<impl IntoResponse, AppError> {
+    let tokens = rotate_session(&state.database_connection, &form.refresh_token).await?;
+
+    Ok((StatusCode::OK, api_response_single(tokens)?))
+}
+
+#[axum::debug_handler]
+pub async fn logout(
+    state: State<Arc<AppState>>,
+    ValidatedJson(form): ValidatedJson<LogoutReq>,
+) -> Result<impl IntoResponse, AppError> {
+    end_session(&state.database_connection, &form.refresh_token, form.all_sessions).await?;
+
+    Ok((StatusCode::OK, api_response_single(json!({ "message": "Logged out" }))?))
+}
+
+#[axum::debug_handler]
+pub async fn forgot_password(
+    state: State<Arc<AppState>>,
+    ValidatedJson(form): ValidatedJson<ForgotPasswordReq>,
+) -> Result<impl IntoResponse, AppError> {
+    let user = user::Entity::find()
+        .filter(user::Column::Username.eq(form.username))
+        .one(&state.database_connection)
+        .await?;
+
+    if let Some(user) = user {
+        request_password_reset(&state.database_connection, state.notifier.as_ref(), &user).await?;
+    }
+
+    // Same answer either way so usernames can't be probed
+    Ok((StatusCode::OK, api_response_single(json!({
+        "message": "If the account exists, a reset token has been sent"
+    }))?))
+}
+
+#[axum::debug_handler]
+pub async fn reset_password(
+    state: State<Arc<AppState>>,
+    ValidatedJson(form): ValidatedJson<ResetPasswordReq>,
+) -> Result<impl IntoResponse, AppError> {
+    redeem_password_reset(&state.database_connection, &form.token, &form.new_password).await?;
+
+    Ok((StatusCode::OK, api_response_single(json!({ "message": "Password has been reset" }))?))
+}
+
+/// Run a check of the caller's own second factor through the login throttle,
+/// so a stolen access token can't be used to guess codes. Only a wrong code
+/// counts as a failure.
+async fn throttled<T>(
+    throttle: &LoginThrottle,
+    username: &str,
+    ip: &str,
+    check: impl Future<Output = Result<T, AppError>>,
+) -> Result<T, AppError> {
+    throttle.begin(username, ip).await?;
+    match check.await {
+        Ok(value) => {
+            throttle.record_success(username, ip).await?;
+            Ok(value)
+        }
+        Err(err @ AppError::Unauthorized(_)) => Err(err),
+        Err(err) => {
+            throttle.cancel(username, ip).await?;
+            Err(err)
+        }
+    }
+}
+
+#[axum::debug_handler]
+pub async fn two_factor_setup(
+    state: State<Arc<AppState>>,
+    auth: AuthUser,
+) -> Result<impl IntoResponse, AppError> {
+    auth.require_session("manage two-factor authentication")?;
+    let user_model = find_user(&state.database_connection, &auth).await?;
+    let (secret, otpauth_uri) = begin_enrollment(&state.database_connection, user_model).await?;
+
+    Ok((StatusCode::OK, api_response_single(json!({
+        "secret": secret,
+        "otpauth_uri": otpauth_uri
+    }))?))
+}
+
+#[axum::debug_handler]
+pub async fn two_factor_confirm(
+    state: State<Arc<AppState>>,
+    auth: AuthUser,
+    ConnectInfo(peer): ConnectInfo<SocketAddr>,
+    headers: HeaderMap,
+    ValidatedJson(form): ValidatedJson<TotpCodeReq>,
+) -> Result<impl IntoResponse, AppError> {
+    auth.require_session("manage two-factor authentication")?;
+    let user_model = find_user(&state.database_connection, &auth).await?;
+    let ip = client_ip(&headers, peer);
+    let recovery_codes = throttled(
+        &state.login_throttle,
+        &auth.username,
+        &ip,
+        confirm_enrollment(&state.database_connection, user_model, &form.code),
+    )
+    .await?;
+
+    Ok((StatusCode::OK, api_response_single(json!({
+        "message": "Two-factor authentication enabled",
+        "recovery_codes": recovery_codes
+    }))?))
+}
+
+#[axum::debug_handler]
+pub async fn two_factor_disable(
+    state: State<Arc<AppState>>,
+    auth: AuthUser,
+    ConnectInfo(peer): ConnectInfo<SocketAddr>,
+    headers: HeaderMap,
+    ValidatedJson(form): ValidatedJson<SecondFactorReq>,
+) -> Result<impl IntoResponse, AppError> {
+    auth.require_session("manage two-factor authentication")?;
+    let user_model = find_user(&state.database_connection, &auth).await?;
+    let factor = SecondFactor {
+        code: form.code.as_deref(),
+        recovery_code: form.recovery_code.as_deref(),
+    };
+    let ip = client_ip(&headers, peer);
+    throttled(
+        &state.login_throttle,
+        &auth.username,
+        &ip,
+        disable(&state.database_connection, user_model, factor),
+    )
+    .await?;
+
+    Ok((StatusCode::OK, api_response_single(json!({ "message": "Two-factor authentication disabled" }))?))
+}
+
+/// Second login step: trade the `mfa_token` from `/auth/login` and a code for a session
+#[axum::debug_handler]
+pub async fn two_factor_verify(
+    state: State<Arc<AppState>>,
+    ConnectInfo(peer): ConnectInfo<SocketAddr>,
+    headers: HeaderMap,
+    ValidatedJson(form): ValidatedJson<MfaVerifyReq>,
+) -> Result<impl IntoResponse, AppError> {
+    let claims = decode_token(&form.mfa_token, TokenKind::Mfa)
+        .map_err(|_| AppError::unauthorized("Invalid or expired MFA token"))?;
+
+    let ip = client_ip(&headers, peer);
+    let throttle = &state.login_throttle;
+    throttle.begin(&claims.username, &ip).await?;
+
+    let user_model = user::Entity::find_by_id(claims.sub)
+        .one(&state.database_connection)
+        .await?
+        .ok_or_else(|| AppError::unauthorized("Invalid or expired MFA token"))?;
+
+    let factor = SecondFactor {
+        code: form.code.as_deref(),
+        recovery_code: form.recovery_code.as_deref(),
+    };
+    // A wrong code stays counted as the failure begin recorded
+    verify_second_factor(&state.database_connection, &user_model, factor).await?;
+
+    throttle.record_success(&claims.username, &ip).await?;
+    let tokens = start_session(&state.database_connection, &user_model).await?;
+
+    Ok((StatusCode::OK, api_response_single(login_payload(user_model, tokens))?))
+}
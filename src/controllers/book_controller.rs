@@ -1,49 +1,140 @@
-use std::collections::HashMap;
-use std::hash::Hash;
 use crate::utils::AppState;
-use axum::extract::{Multipart, Path, Query, State};
-use entity::book::Entity;
-use sea_orm::{ActiveModelTrait, DatabaseConnection, DbErr, EntityTrait, ModelTrait, QueryFilter, Set, ColumnTrait, QueryOrder, QuerySelect, QueryTrait};
+use axum::extract::{Multipart, OriginalUri, Path, State};
+use sea_orm::{
+    ActiveModelTrait, ColumnTrait, ConnectionTrait, DatabaseConnection, EntityTrait, IntoActiveModel,
+    ModelTrait, Order, QueryFilter, QueryOrder, QuerySelect, QueryTrait, Set, TransactionTrait,
+};
 use serde_json::json;
 use std::sync::Arc;
 use axum::http::StatusCode;
-use axum::Json;
 use axum::response::{IntoResponse, Response};
+use serde::Deserialize;
 use chrono::Utc;
-use futures::TryStreamExt;
 use tracing::info;
 use uuid::Uuid;
-use entity::{book, book_category, category, post, post_category, user};
-use crate::app::files::files::{path_storage, write_file};
-use crate::app::files::validator::sanitize_filename;
-use crate::respons::{api_response, api_response_single};
-use crate::routes::{internal_error, not_found_error};
+use entity::{book, book_category, category};
+use crate::app::auth::extractor::AuthUser;
+use crate::app::auth::policy::ensure_owner;
+use crate::app::files::files::{path_storage, write_stream, TempFile};
+use crate::app::files::policy::{UploadKind, UploadPolicy};
+use crate::app::files::session::UploadSession;
+use crate::app::hashing::hash::{Checksum, FileHasher};
+use crate::loader::book_categories;
+use crate::pagination::{fetch_page, validate_page_params, Cursor, PageRequest};
+use crate::respons::{api_response_paged, api_response_single};
+use crate::search::any_contains;
+use crate::error::AppError;
+use crate::validation::{Validate, ValidatedJson, ValidatedQuery, Validator, SLUG_PATTERN, USERNAME_PATTERN};
+
+const SORT_FIELDS: &[&str] = &["title", "created_at", "updated_at"];
+const SORT_ORDERS: &[&str] = &["asc", "desc"];
+
+#[derive(Debug, Deserialize)]
+pub struct BookQuery {
+    /// Case-insensitive, matches title, writer or publisher
+    search: Option<String>,
+    writer: Option<String>,
+    publisher: Option<String>,
+    uploader: Option<String>,
+    category: Option<String>,
+    sort: Option<String>,
+    order: Option<String>,
+    page: Option<u64>,
+    limit: Option<u64>,
+    cursor: Option<String>,
+}
+
+impl BookQuery {
+    /// Column and direction asked for with `sort` and `order`, `None` keeps newest first.
+    /// `order` alone sorts by `created_at`.
+    fn ordering(&self) -> Option<(book::Column, Order)> {
+        if self.sort.is_none() && self.order.is_none() {
+            return None;
+        }
+        let column = match self.sort.as_deref() {
+            Some("title") => book::Column::Title,
+            Some("updated_at") => book::Column::UpdatedAt,
+            _ => book::Column::CreatedAt,
+        };
+        let order = match self.order.as_deref() {
+            Some("asc") => Order::Asc,
+            _ => Order::Desc,
+        };
+        Some((column, order))
+    }
+}
+
+impl Validate for BookQuery {
+    fn validate(&self) -> Result<(), AppError> {
+        let mut v = Validator::new();
+        if let Some(search) = &self.search {
+            v.length("search", search, 1, 100);
+        }
+        if let Some(writer) = &self.writer {
+            v.length("writer", writer, 1, 255);
+        }
+        if let Some(publisher) = &self.publisher {
+            v.length("publisher", publisher, 1, 255);
+        }
+        if let Some(uploader) = &self.uploader {
+            v.pattern("uploader", uploader, &USERNAME_PATTERN, "letters, digits, '_', '.' and '-'");
+        }
+        if let Some(category) = &self.category {
+            v.pattern("category", category, &SLUG_PATTERN, "lowercase letters, digits and '-'");
+        }
+        if let Some(sort) = &self.sort {
+            v.one_of("sort", sort, SORT_FIELDS);
+        }
+        if let Some(order) = &self.order {
+            v.one_of("order", order, SORT_ORDERS);
+        }
+        validate_page_params(&mut v, self.page, self.limit, self.cursor.as_deref());
+        // Cursors only encode the position in the default order
+        let default_order = matches!(self.sort.as_deref(), None | Some("created_at"))
+            && self.order.as_deref() != Some("asc");
+        if self.cursor.is_some() && !default_order {
+            v.check("cursor", false, "only works with the default sort, newest first");
+        }
+        v.finish()
+    }
+}
 
 #[axum::debug_handler]
-pub async fn list_books(_state: State<Arc<AppState>>, Query(params): Query<HashMap<String, String>>)
-    -> Result<impl IntoResponse, (StatusCode, String)>
+pub async fn list_books(
+    _state: State<Arc<AppState>>,
+    OriginalUri(uri): OriginalUri,
+    ValidatedQuery(params): ValidatedQuery<BookQuery>,
+)
+    -> Result<impl IntoResponse, AppError>
 {
-    let search = params.get("search").cloned();
-    let user_filter = params.get("uploader").cloned();
-    let category_slug = params.get("category").cloned();
-
-    let page: u64 = params
-        .get("page")
-        .and_then(|v| v.parse().ok())
-        .unwrap_or(1);
-    let limit: u64 = params
-        .get("limit")
-        .and_then(|v| v.parse().ok())
-        .unwrap_or(10);
-    let offset = (page - 1) * limit;
-
-    let mut query = book::Entity::find().find_also_related(entity::user::Entity)
-        .order_by_desc(book::Column::CreatedAt);
-
-    // Filter title
-    if let Some(title) = search {
-        let pattern = format!("%{}%", title);
-        query = query.filter(book::Column::Title.eq(pattern));
+    let ordering = params.ordering();
+    let search = params.search;
+    let user_filter = params.uploader;
+    let category_slug = params.category;
+
+    let page_request = PageRequest::new(params.page, params.limit, params.cursor.as_deref())?;
+
+    let mut query = book::Entity::find().find_also_related(entity::user::Entity);
+
+    // Filter title, writer or publisher
+    if let Some(keyword) = search {
+        query = query.filter(any_contains(
+            &[book::Column::Title, book::Column::Writer, book::Column::Publisher],
+            &keyword,
+        ));
+    }
+
+    // Exact writer / publisher
+    if let Some(writer) = params.writer {
+        query = query.filter(book::Column::Writer.eq(writer));
+    }
+    if let Some(publisher) = params.publisher {
+        query = query.filter(book::Column::Publisher.eq(publisher));
+    }
+
+    // Asked order first, fetch_page breaks ties newest first
+    if let Some((column, order)) = ordering {
+        query = query.order_by(column, order);
     }
 
     // Filter user
@@ -51,12 +142,11 @@ pub async fn list_books(_state: State<Arc<AppState>>, Query(params): Query<HashM
         if let Some(user) = entity::user::Entity::find()
             .filter(entity::user::Column::Username.eq(username))
             .one(&_state.database_connection)
-            .await
-            .map_err(internal_error)?
+            .await?
         {
             query = query.filter(book::Column::UserId.eq(user.id));
         } else {
-            return Ok(api_response(Vec::<serde_json::Value>::new())); // User isn't found → kosong
+            return api_response_paged(Vec::<serde_json::Value>::new(), page_request.empty_meta(&uri)); // User isn't found → kosong
         }
     }
 
@@ -65,8 +155,7 @@ pub async fn list_books(_state: State<Arc<AppState>>, Query(params): Query<HashM
         if let Some(cat) = entity::category::Entity::find()
             .filter(category::Column::Slug.eq(slug))
             .one(&_state.database_connection)
-            .await
-            .map_err(internal_error)?
+            .await?
         {
             query = query
                 .filter(book::Column::Id.in_subquery(
@@ -77,74 +166,70 @@ pub async fn list_books(_state: State<Arc<AppState>>, Query(params): Query<HashM
                         .into_query(),
                 ));
         } else {
-            return Ok(api_response(Vec::<serde_json::Value>::new())); // Category isn't found → kosong
+            return api_response_paged(Vec::<serde_json::Value>::new(), page_request.empty_meta(&uri)); // Category isn't found → kosong
         }
     }
 
-    let book_with_users = query
-        .offset(offset)
-        .limit(limit)
-        .all(&_state.database_connection).await
-        .map_err(internal_error)?;
+    let (book_with_users, meta) = fetch_page(
+        &_state.database_connection,
+        query,
+        page_request,
+        &uri,
+        (book::Column::CreatedAt, book::Column::Id),
+        |book| Cursor { created_at: book.created_at, id: book.id },
+    )
+    .await?;
 
-    let mut data = Vec::new();
-    for (book_model, user_opt) in book_with_users {
-        // Categories
-        let categories = book::Entity::find()
-            .filter(book_category::Column::BookId.eq(book_model.id))
-            .all(&_state.database_connection)
-            .await
-            .expect("Book collection does not exist")
-            .into_iter()
-            .map(|rel| rel.id)
-            .collect::<Vec<_>>();
-
-        // Ambil uploader (user relasi)
-        // let uploader = book_model
-        //     .find_related(user::Entity)
-        //     .one(&_state.database_connection)
-        //     .await
-        //     .expect("User is banned or smth")
-        //     .map(|u| json!({
-        //         "id": u.id,
-        //         "name": u.name,
-        //     }));
+    let ids = book_with_users.iter().map(|(book, _)| book.id).collect::<Vec<_>>();
+    let mut categories = book_categories(&_state.database_connection, &ids).await?;
 
+    let mut data = Vec::with_capacity(book_with_users.len());
+    for (book_model, user_opt) in book_with_users {
         data.push(json!({
                 "title": book_model.title,
                 "book_file": book_model.book_file,
                 "writer": book_model.writer,
                 "uploader": user_opt.map(|u| u.username),
                 "publisher": book_model.publisher,
-                "categories": categories,
+                "categories": categories.remove(&book_model.id).unwrap_or_default(),
                 "created_at": book_model.created_at,
                 "updated_at": book_model.updated_at,
             }));
     }
-    Ok(api_response(data))
+    api_response_paged(data, meta)
+}
+
+/// `chunkNumber` and `totalChunks` have to be numbers, guessing 0 would
+/// store the chunk in the wrong place
+fn parse_chunk_field(name: &str, raw: &str) -> Result<usize, AppError> {
+    raw.trim()
+        .parse()
+        .map_err(|_| AppError::bad_request(format!("{} must be a non-negative integer", name)))
 }
 
 #[axum::debug_handler]
 pub async fn create_book(
     _state: State<Arc<AppState>>,
+    auth: AuthUser,
     mut payload: Multipart
-) -> impl IntoResponse {
-    use crate::app::files::validator::{validate_book_mime, validate_chunk_size};
+) -> Result<Response, AppError> {
+    let policy = UploadPolicy::for_kind(UploadKind::Book);
 
     let mut title = String::new();
     let mut writer = String::new();
     let mut publisher = String::new();
     let mut file_name = String::new();
     let mut categories = Vec::new();
+    let mut upload_id = None;
+    let mut checksum = None;
 
     let mut chunk_number = 0;
     let mut total_chunks = 0;
-    let mut chunk_data = Vec::new();
+    // chunkData goes straight to disk
+    let mut chunk: Option<TempFile> = None;
+    let mut chunk_digest = String::new();
 
-    while let Some(field) = match payload.next_field().await {
-        Ok(f) => f,
-        Err(_) => return StatusCode::BAD_REQUEST.into_response(),
-    } {
+    while let Some(field) = payload.next_field().await? {
         match field.name().unwrap_or("") {
             "title" => title = field.text().await.unwrap_or_default(),
             "writer" => writer = field.text().await.unwrap_or_default(),
@@ -156,112 +241,254 @@ pub async fn create_book(
                     categories.extend(parsed);
                 }
             },
-            "chunkNumber" => {
-                chunk_number = field.text().await.unwrap_or_default().parse().unwrap_or(0);
-            }
-            "totalChunks" => {
-                total_chunks = field.text().await.unwrap_or_default().parse().unwrap_or(0);
-            }
+            "uploadId" => upload_id = Some(field.text().await.unwrap_or_default()),
+            "chunkChecksum" => checksum = Some(Checksum::parse(&field.text().await.unwrap_or_default())?),
+            "chunkNumber" => chunk_number = parse_chunk_field("chunkNumber", &field.text().await?)?,
+            "totalChunks" => total_chunks = parse_chunk_field("totalChunks", &field.text().await?)?,
             "chunkData" => {
-                chunk_data = field.bytes().await.unwrap_or_default().to_vec();
+                if file_name.is_empty() {
+                    file_name = field.file_name().unwrap_or_default().to_string();
+                }
+                let mut temp = TempFile::create().await?;
+                let mut hasher = FileHasher::new();
+                let written = write_stream(field, &mut temp.file, policy.chunk_size, |bytes| {
+                    hasher.update(bytes)
+                }).await?;
+                chunk_digest = hasher.finalize();
+                chunk = (written > 0).then_some(temp);
             }
             _ => {}
         }
     }
 
-    // // File name with time chrono
-    let timestamp = Utc::now().format("%Y-%m-%d_%H-%M-%S").to_string();
-    let sanitized_name = sanitize_filename(&file_name);
-    let final_file_name = format!("{}_{}", &timestamp, sanitized_name);
+    let Some(chunk) = chunk.filter(|_| !title.is_empty()) else {
+        return Err(AppError::bad_request("Title and book file required"));
+    };
 
-    if title.is_empty() || chunk_data.is_empty() {
-        return (StatusCode::BAD_REQUEST, "Title and book file required").into_response();
-    }
+    // Chunks of one file share an upload session, the first request opens it
+    // and answers with the uploadId the next chunks have to send
+    let session = match upload_id {
+        Some(id) => {
+            let id = Uuid::parse_str(&id).map_err(|_| AppError::bad_request("Invalid uploadId"))?;
+            let session = UploadSession::load(id, auth.id).await?;
+            if session.kind != UploadKind::Book {
+                return Err(AppError::bad_request("uploadId is not a book upload"));
+            }
+            if total_chunks != 0 && total_chunks != session.total_chunks {
+                return Err(AppError::bad_request(format!(
+                    "totalChunks must stay {}",
+                    session.total_chunks
+                )));
+            }
+            session
+        }
+        None => UploadSession::create(auth.id, UploadKind::Book, &file_name, total_chunks.max(1)).await?,
+    };
 
-    // ✅ Validasi MIME dan size
-    if let Err(e) = validate_book_mime(&chunk_data) {
-        return (StatusCode::UNSUPPORTED_MEDIA_TYPE, e.to_string()).into_response();
-    }
+    // Simpan chunk ke file, chunks may come in any order. The session checks
+    // the book policy: total size, and extension against content on chunk 0.
+    session.store_chunk(chunk_number, chunk, &chunk_digest, checksum.as_ref()).await?;
 
-    if let Err(e) = validate_chunk_size(&chunk_data, 5) {
-        return (StatusCode::PAYLOAD_TOO_LARGE, e.to_string()).into_response();
-    }
+    // Gabungkan semua once every chunk is in, whichever arrived last. The
+    // book fields of that request are the ones stored.
+    if session.status().await?.complete {
+        // Refuse bad fields while the chunks can still be resent with better ones
+        BookReq {
+            title: title.clone(),
+            writer: writer.clone(),
+            publisher: publisher.clone(),
+            categories: Some(categories.clone()),
+        }
+        .validate()?;
 
-    let upload_dir = path_storage(&format!("uploads/{}/chunk", timestamp));
-    let chunk_path = upload_dir.join(chunk_number.to_string());
-    let file_path = format!("/uploads/books/{}", final_file_name);
+        let file_out = match session.complete().await {
+            Ok(file_out) => file_out,
+            // A parallel request with the other last chunk got there first
+            Err(AppError::NotFound(_)) => {
+                return Ok(api_response_single(json!({ "upload_id": session.id }))?.into_response());
+            }
+            Err(err) => return Err(err),
+        };
+        let file_path = format!("/{}", file_out.0);
 
-    // Simpan chunk ke file
-    if let Err(err) = tokio::fs::write(&chunk_path, chunk_data).await {
-        eprintln!("Error writing chunk: {:?}", err);
-        return StatusCode::INTERNAL_SERVER_ERROR.into_response();
+        let stored = store_book(&_state.database_connection, auth.id, NewBook {
+            title,
+            writer,
+            publisher,
+            book_file: file_path,
+            categories,
+        }).await;
+        let book = match stored {
+            Ok(book) => book,
+            Err(err) => {
+                session.restore(&file_out.0).await?;
+                return Err(err);
+            }
+        };
+        session.forget().await;
+
+        info!("Book uploaded by {}: {}, Hash: {}", auth.username, file_out.0, file_out.1);
+        return Ok(api_response_single(json!({
+            "upload_id": session.id,
+            "book": book,
+            "hash": file_out.1,
+        }))?.into_response());
     }
 
-    // Jika ini adalah chunk terakhir, gabungkan semua
-    if chunk_number + 1 == total_chunks {
-        let chunk_relative_path = format!("uploads/{}/chunk", timestamp);
-
-        return match write_file(&chunk_relative_path, &final_file_name, total_chunks, Some("books"))
-            .await {
-            Ok(file_out) => {
-                use sea_orm::ActiveValue::Set;
-                let b = book::ActiveModel {
-                    title: Set(title),
-                    writer: Set(writer),
-                    publisher: Set(publisher),
-                    book_file: Set(file_path),
-                    created_at: Set(Utc::now()),
-                    ..Default::default()
-                }.insert(&_state.database_connection)
-                    .await
-                    .expect("Error insert");
-                attach_categories_to_book(
-                    &_state.database_connection,
-                    b.id,
-                    categories
-                ).await.expect("Error attach");
-
-                info!("Book uploaded: {}, Hash: {}", final_file_name, file_out.1);
-                (StatusCode::OK, format!("Hash: {}", file_out.1)).into_response()
-            }
-            Err((status, msg)) => {
-                (status, msg).into_response()
+    Ok(api_response_single(json!({ "upload_id": session.id }))?.into_response())
+}
+
+/// A book whose file is already stored
+pub struct NewBook {
+    pub title: String,
+    pub writer: String,
+    pub publisher: String,
+    /// Path under `storage`, with a leading `/`
+    pub book_file: String,
+    pub categories: Vec<String>,
+}
+
+/// Insert a finished upload as a book of `user_id`, shared by the multipart
+/// and the tus uploads
+pub async fn store_book(
+    db: &DatabaseConnection,
+    user_id: Uuid,
+    new_book: NewBook,
+) -> Result<book::Model, AppError> {
+    // A book without the categories it was sent with is never visible
+    let txn = db.begin().await?;
+    let b = book::ActiveModel {
+        title: Set(new_book.title),
+        writer: Set(new_book.writer),
+        publisher: Set(new_book.publisher),
+        book_file: Set(new_book.book_file),
+        user_id: Set(Some(user_id)),
+        created_at: Set(Utc::now()),
+        ..Default::default()
+    }.insert(&txn)
+        .await?;
+    attach_categories_to_book(&txn, b.id, new_book.categories).await?;
+    txn.commit().await?;
+
+    Ok(b)
+}
+
+#[derive(Debug, Deserialize)]
+pub struct BookReq {
+    title: String,
+    writer: String,
+    publisher: String,
+    categories: Option<Vec<String>>,
+}
+
+impl Validate for BookReq {
+    fn validate(&self) -> Result<(), AppError> {
+        let mut v = Validator::new();
+        v.length("title", &self.title, 1, 200)
+            .length("writer", &self.writer, 1, 100)
+            .length("publisher", &self.publisher, 1, 100);
+        if let Some(categories) = &self.categories {
+            v.range("categories", categories.len(), 0, 20);
+            for (i, name) in categories.iter().enumerate() {
+                v.length(&format!("categories[{}]", i), name, 1, 50);
             }
         }
+        v.finish()
     }
+}
 
-    StatusCode::OK.into_response()
+#[axum::debug_handler]
+pub async fn update_book(
+    _state: State<Arc<AppState>>,
+    auth: AuthUser,
+    Path(title): Path<String>,
+    ValidatedJson(form): ValidatedJson<BookReq>,
+) -> Result<impl IntoResponse, AppError> {
+    let db = &_state.database_connection;
+    let old_book = book::Entity::find()
+        .filter(book::Column::Title.eq(title))
+        .one(db)
+        .await?
+        .ok_or_else(|| AppError::not_found("Book not found"))?;
+
+    ensure_owner(&auth, old_book.user_id)?;
+
+    let book_id = old_book.id;
+    let mut book = old_book.into_active_model();
+    book.title = Set(form.title);
+    book.writer = Set(form.writer);
+    book.publisher = Set(form.publisher);
+    book.updated_at = Set(Utc::now());
+
+    let txn = db.begin().await?;
+    let updated = book.update(&txn).await?;
+
+    // Replace the category set only when the client sent one
+    if let Some(categories) = form.categories {
+        book_category::Entity::delete_many()
+            .filter(book_category::Column::BookId.eq(book_id))
+            .exec(&txn)
+            .await?;
+        attach_categories_to_book(&txn, book_id, categories)
+            .await?;
+    }
+    txn.commit().await?;
+
+    Ok((StatusCode::OK, api_response_single(updated)?))
+}
+
+#[axum::debug_handler]
+pub async fn delete_book(
+    _state: State<Arc<AppState>>,
+    auth: AuthUser,
+    Path(title): Path<String>,
+) -> Result<impl IntoResponse, AppError> {
+    let db = &_state.database_connection;
+    let book = book::Entity::find()
+        .filter(book::Column::Title.eq(title))
+        .one(db)
+        .await?
+        .ok_or_else(|| AppError::not_found("Book not found"))?;
+
+    ensure_owner(&auth, book.user_id)?;
+
+    let book_file = path_storage(book.book_file.trim_start_matches('/'));
+    book.delete(db).await?;
+
+    // Best effort: the row is gone, a leftover file is only wasted space
+    if let Err(err) = tokio::fs::remove_file(&book_file).await {
+        info!("Could not remove {:?}: {}", book_file, err);
+    }
+    tokio::fs::remove_file(book_file.with_extension("hash")).await.ok();
+
+    Ok((StatusCode::OK, api_response_single(json!({ "message": "Book deleted successfully" }))?))
 }
 
 pub async fn get_book(
     _state: State<Arc<AppState>>,
     Path(title): Path<String>,
-) -> impl IntoResponse {
+) -> Result<impl IntoResponse, AppError> {
     let book = book::Entity::find()
         .filter(book::Column::Title.eq(title))
         .one(&_state.database_connection)
-        .await
-        .expect("Not found");
+        .await?
+        .ok_or_else(|| AppError::not_found("Book not found"))?;
 
-    match book {
-        Some(book) => (StatusCode::OK, api_response_single(book)).into_response(),
-        None => not_found_error("Book not found").into_response(),
-    }
+    Ok((StatusCode::OK, api_response_single(book)?))
 }
 
 pub async fn attach_categories_to_book(
-    state: &DatabaseConnection,
+    state: &impl ConnectionTrait,
     book_id: Uuid,
     categories: Vec<String>,
-) -> Result<(StatusCode, String), (StatusCode, String)> {
+) -> Result<(), AppError> {
     for name in categories {
         let cat = category::Entity::find()
             .filter(category::Column::Name.eq(name.clone()))
             .one(state)
-            .await
-            .expect("Error finding category");
+            .await?;
         let Some(cat) = cat else {
-            return Err(not_found_error("category not found"));
+            return Err(AppError::not_found("category not found"));
         };
 
         // Buat relasi book - category
@@ -270,8 +497,8 @@ pub async fn attach_categories_to_book(
             category_id: Set(cat.id),
         };
 
-        rel.insert(state).await.expect("Error insert");
+        rel.insert(state).await?;
     }
 
-    Ok((StatusCode::OK, "Categories saved".to_string()))
+    Ok(())
 }
\ No newline at end of file
@@ -1,15 +1,15 @@
-use crate::respons::{api_response, api_response_single};
-use crate::routes::internal_error;
+use crate::pagination::{validate_page_params, PageMeta, Pagination};
+use crate::respons::{api_response_paged, api_response_single};
+use crate::error::AppError;
+use crate::validation::{Validate, ValidatedJson, ValidatedQuery, Validator};
 use crate::utils::{slugify, AppState};
-use axum::extract::{Query, State};
+use axum::extract::{OriginalUri, Path, State};
 use axum::http::StatusCode;
 use axum::response::IntoResponse;
-use axum::Json;
 use entity::category;
-use sea_orm::{ActiveModelTrait, ColumnTrait, EntityTrait, QueryFilter, Set};
+use sea_orm::{ActiveModelTrait, ColumnTrait, EntityTrait, IntoActiveModel, ModelTrait, PaginatorTrait, QueryFilter, QueryOrder, QuerySelect, Set};
 use serde::Deserialize;
 use serde_json::json;
-use std::collections::HashMap;
 use std::sync::Arc;
 
 #[derive(Deserialize)]
@@ -17,11 +17,35 @@ pub struct CategoryReq {
     name: String,
 }
 
+impl Validate for CategoryReq {
+    fn validate(&self) -> Result<(), AppError> {
+        Validator::new().length("name", self.name.trim(), 1, 50).finish()
+    }
+}
+
+#[derive(Deserialize)]
+pub struct CategoryQuery {
+    s: Option<String>,
+    page: Option<u64>,
+    limit: Option<u64>,
+}
+
+impl Validate for CategoryQuery {
+    fn validate(&self) -> Result<(), AppError> {
+        let mut v = Validator::new();
+        if let Some(keyword) = &self.s {
+            v.length("s", keyword, 1, 100);
+        }
+        validate_page_params(&mut v, self.page, self.limit, None);
+        v.finish()
+    }
+}
+
 #[axum::debug_handler]
 pub async fn create_category(
     _state: State<Arc<AppState>>,
-    Json(payload): Json<CategoryReq>
-) -> impl IntoResponse {
+    ValidatedJson(payload): ValidatedJson<CategoryReq>
+) -> Result<impl IntoResponse, AppError> {
     let slug = slugify(&payload.name);
     let category = category::ActiveModel {
         name: Set(payload.name.to_owned()),
@@ -30,17 +54,19 @@ pub async fn create_category(
     };
 
     match category.insert(&_state.database_connection).await {
-        Ok(inserted_category) => Ok((StatusCode::CREATED, api_response_single(inserted_category))),
-        Err(err) => Err(internal_error(err))
+        Ok(inserted_category) => Ok((StatusCode::CREATED, api_response_single(inserted_category)?)),
+        Err(err) => Err(AppError::from(err))
     }
 }
 
 #[axum::debug_handler]
 pub async fn list_categories(
     _state: State<Arc<AppState>>,
-    Query(query): Query<HashMap<String, String>>
-) -> impl IntoResponse {
-    let keyword = query.get("s").cloned();
+    OriginalUri(uri): OriginalUri,
+    ValidatedQuery(query): ValidatedQuery<CategoryQuery>
+) -> Result<impl IntoResponse, AppError> {
+    let keyword = query.s;
+    let pagination = Pagination::new(query.page, query.limit);
 
     let mut find = category::Entity::find();
 
@@ -51,11 +77,13 @@ pub async fn list_categories(
         );
     };
 
+    let total = find.clone().count(&_state.database_connection).await?;
     let category = find
+        .order_by_asc(category::Column::Name)
+        .offset(pagination.offset())
+        .limit(pagination.limit)
         .all(&_state.database_connection)
-        .await
-        .map_err(|e| internal_error(e.to_string()))
-        .expect("Error finding categories");
+        .await?;
 
     let category = category
         .into_iter()
@@ -64,5 +92,44 @@ pub async fn list_categories(
             "name": category.name,
             "slug": category.slug,
         })).collect::<Vec<_>>();
-    api_response(category)
-}
\ No newline at end of file
+    api_response_paged(category, PageMeta::new(&uri, pagination, total))
+}
+
+#[axum::debug_handler]
+pub async fn update_category(
+    _state: State<Arc<AppState>>,
+    Path(slug): Path<String>,
+    ValidatedJson(payload): ValidatedJson<CategoryReq>
+) -> Result<impl IntoResponse, AppError> {
+    let old_category = category::Entity::find()
+        .filter(category::Column::Slug.eq(slug))
+        .one(&_state.database_connection)
+        .await?
+        .ok_or_else(|| AppError::not_found("Category not found"))?;
+
+    let mut category = old_category.into_active_model();
+    category.name = Set(payload.name.to_owned());
+    category.slug = Set(slugify(&payload.name));
+
+    let updated = category
+        .update(&_state.database_connection)
+        .await?;
+    Ok((StatusCode::OK, api_response_single(updated)?))
+}
+
+#[axum::debug_handler]
+pub async fn delete_category(
+    _state: State<Arc<AppState>>,
+    Path(slug): Path<String>,
+) -> Result<impl IntoResponse, AppError> {
+    let category = category::Entity::find()
+        .filter(category::Column::Slug.eq(slug))
+        .one(&_state.database_connection)
+        .await?
+        .ok_or_else(|| AppError::not_found("Category not found"))?;
+
+    category
+        .delete(&_state.database_connection)
+        .await?;
+    Ok((StatusCode::OK, api_response_single(json!({ "message": "Category deleted successfully" }))?))
+}
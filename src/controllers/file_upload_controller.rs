@@ -1,14 +1,9 @@
-use std::path::Path;
-use crate::app::files::files::{path_storage, write_file};
 use axum::extract::Multipart;
 use axum::http::StatusCode;
 use axum::response::IntoResponse;
-use tokio::fs;
-use tracing::log::info;
-use crate::app::files::validator::{ timestamped_filename, sanitize_filename };
 
 #[axum::debug_handler]
-pub async fn upload(mut payload: Multipart) -> impl IntoResponse {
+pub async fn upload(_payload: Multipart) -> impl IntoResponse {
     // let mut file_name = String::new();
     // let mut chunk_number = 0;
     // let mut total_chunks = 0;
@@ -4,3 +4,8 @@ pub mod file_upload_controller;
 pub mod user_controller;
 pub mod book_controller;
 pub mod category_controller;
+pub mod auth_controller;
+pub mod api_key_controller;
+pub mod search_controller;
+pub mod upload_session_controller;
+pub mod tus_controller;
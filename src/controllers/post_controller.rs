@@ -1,54 +1,109 @@
-use std::collections::HashMap;
+use crate::app::auth::extractor::AuthUser;
+use crate::app::auth::policy::ensure_owner;
 use crate::utils::{slugify, AppState};
-use crate::respons::{api_response, api_response_single};
-use crate::routes::{internal_error, not_found_error};
-use axum::extract::{Path, Query, State};
+use crate::loader::post_categories;
+use crate::pagination::{fetch_page, validate_page_params, Cursor, PageRequest};
+use crate::search::{any_contains, full_text_available, post_matches, post_rank, post_snippets};
+use crate::respons::{api_response, api_response_paged, api_response_single};
+use crate::error::AppError;
+use crate::validation::{Validate, ValidatedJson, ValidatedQuery, Validator, SLUG_PATTERN, USERNAME_PATTERN};
+use axum::extract::{OriginalUri, Path, State};
 use axum::http::StatusCode;
 use axum::response::IntoResponse;
-use axum::Json;
-use entity::post::{ActiveModel, Column};
+use entity::post::Column;
 use entity::prelude::Post;
-use sea_orm::{ActiveModelTrait, EntityOrSelect, EntityTrait, IntoActiveModel, QueryFilter, Set, ColumnTrait, DatabaseConnection, DbErr, ModelTrait, TransactionTrait, QueryOrder, QuerySelect, QueryTrait};
+use sea_orm::{ActiveModelTrait, EntityTrait, IntoActiveModel, QueryFilter, Set, ColumnTrait, DatabaseConnection, Order, QueryOrder, TransactionTrait, QuerySelect, QueryTrait};
+use std::collections::HashMap;
 use serde::Deserialize;
 use serde_json::json;
 use std::sync::Arc;
 use tracing::info;
 use uuid::Uuid;
-use entity::{category, post, post_category};
+use entity::{category, post, post_attachment, post_category};
+use chrono::Utc;
 
 #[derive(Debug, Deserialize)]
 pub struct PostReq {
     title: String,
     text: String,
     categories: Vec<String>,
-    uploader: String
+}
+
+impl Validate for PostReq {
+    fn validate(&self) -> Result<(), AppError> {
+        let mut v = Validator::new();
+        v.length("title", &self.title, 1, 200)
+            .length("text", &self.text, 1, 50_000)
+            .range("categories", self.categories.len(), 0, 20);
+        for (i, name) in self.categories.iter().enumerate() {
+            v.length(&format!("categories[{}]", i), name, 1, 50);
+        }
+        v.finish()
+    }
+}
+
+#[derive(Debug, Deserialize)]
+pub struct PostQuery {
+    search: Option<String>,
+    /// `title` (default) or `fulltext`, ranked with snippets on PostgreSQL
+    mode: Option<String>,
+    user: Option<String>,
+    category: Option<String>,
+    page: Option<u64>,
+    limit: Option<u64>,
+    cursor: Option<String>,
+}
+
+impl Validate for PostQuery {
+    fn validate(&self) -> Result<(), AppError> {
+        let mut v = Validator::new();
+        if let Some(search) = &self.search {
+            v.length("search", search, 1, 100);
+        }
+        if let Some(mode) = &self.mode {
+            v.one_of("mode", mode, &["title", "fulltext"]);
+        }
+        if let Some(user) = &self.user {
+            v.pattern("user", user, &USERNAME_PATTERN, "letters, digits, '_', '.' and '-'");
+        }
+        if let Some(category) = &self.category {
+            v.pattern("category", category, &SLUG_PATTERN, "lowercase letters, digits and '-'");
+        }
+        validate_page_params(&mut v, self.page, self.limit, self.cursor.as_deref());
+        // Ranked results have no stable position to resume from
+        if self.cursor.is_some() && self.mode.as_deref() == Some("fulltext") {
+            v.check("cursor", false, "can't be combined with fulltext mode");
+        }
+        v.finish()
+    }
 }
 
 #[axum::debug_handler]
 pub async fn list_posts(
     state: State<Arc<AppState>>,
-    Query(params): Query<HashMap<String, String>>,
-) -> Result<impl IntoResponse, (StatusCode, String)> {
-    let search = params.get("search").cloned();
-    let user_filter = params.get("user").cloned();
-    let category_slug = params.get("category").cloned();
-
-    let page: u64 = params
-        .get("page")
-        .and_then(|v| v.parse().ok())
-        .unwrap_or(1);
-    let limit: u64 = params
-        .get("limit")
-        .and_then(|v| v.parse().ok())
-        .unwrap_or(10);
-    let offset = (page - 1) * limit;
-
-    let mut query = post::Entity::find().find_also_related(entity::user::Entity)
-        .order_by_desc(post::Column::CreatedAt);
-
-    // Filter title
-    if let Some(kw) = search {
-        query = query.filter(post::Column::Title.contains(kw));
+    OriginalUri(uri): OriginalUri,
+    ValidatedQuery(params): ValidatedQuery<PostQuery>,
+) -> Result<impl IntoResponse, AppError> {
+    let search = params.search;
+    let full_text = params.mode.as_deref() == Some("fulltext");
+    let ranked = full_text && full_text_available(&state.database_connection);
+    let user_filter = params.user;
+    let category_slug = params.category;
+
+    let page_request = PageRequest::new(params.page, params.limit, params.cursor.as_deref())?;
+
+    let mut query = post::Entity::find().find_also_related(entity::user::Entity);
+
+    // Filter title, or title and text in fulltext mode
+    if let Some(kw) = &search {
+        query = if ranked {
+            query.filter(post_matches(kw)).order_by(post_rank(kw), Order::Desc)
+        } else if full_text {
+            // No tsvector outside PostgreSQL, match with LIKE and keep newest first
+            query.filter(any_contains(&[post::Column::Title, post::Column::Text], kw))
+        } else {
+            query.filter(post::Column::Title.contains(kw))
+        };
     }
 
     // Filter user
@@ -56,12 +111,11 @@ pub async fn list_posts(
         if let Some(user) = entity::user::Entity::find()
             .filter(entity::user::Column::Username.eq(username))
             .one(&state.database_connection)
-            .await
-            .map_err(internal_error)?
+            .await?
         {
             query = query.filter(post::Column::UserId.eq(user.id))
         } else {
-            return Ok(api_response(Vec::<serde_json::Value>::new())); // User isn't found → kosong
+            return api_response_paged(Vec::<serde_json::Value>::new(), page_request.empty_meta(&uri)); // User isn't found → kosong
         }
     };
 
@@ -70,8 +124,7 @@ pub async fn list_posts(
         if let Some(cat) = entity::category::Entity::find()
             .filter(category::Column::Slug.eq(slug))
             .one(&state.database_connection)
-            .await
-            .map_err(internal_error)?
+            .await?
         {
             query = query
                 .filter(post::Column::Id.in_subquery(
@@ -82,27 +135,33 @@ pub async fn list_posts(
                         .into_query(),
                 ));
         } else {
-            return Ok(api_response(Vec::<serde_json::Value>::new())); // Category isn't found → kosong
+            return api_response_paged(Vec::<serde_json::Value>::new(), page_request.empty_meta(&uri)); // Category isn't found → kosong
         }
     }
 
-    let posts_with_user = query
-        .offset(offset)
-        .limit(limit)
-        .all(&state.database_connection).await.map_err(internal_error)?;
+    let (posts_with_user, meta) = fetch_page(
+        &state.database_connection,
+        query,
+        page_request,
+        &uri,
+        (post::Column::CreatedAt, post::Column::Id),
+        |post| Cursor { created_at: post.created_at, id: post.id },
+    )
+    .await?;
+
+    let ids = posts_with_user.iter().map(|(post, _)| post.id).collect::<Vec<_>>();
+    let mut categories = post_categories(&state.database_connection, &ids).await?;
+    let mut snippets = match &search {
+        Some(kw) if ranked => post_snippets(&state.database_connection, kw, &ids).await?,
+        _ => HashMap::new(),
+    };
 
     let mut results = Vec::with_capacity(posts_with_user.len());
 
     for (post_model, user_opt) in posts_with_user {
-        let categories = post_model
-            .find_related(category::Entity)
-            .all(&state.database_connection)
-            .await.map_err(internal_error)?
-            .into_iter()
-            .map(|category| category.name)
-            .collect::<Vec<_>>();
-
-        results.push(json!({
+        let categories = categories.remove(&post_model.id).unwrap_or_default();
+
+        let mut item = json!({
             "id": post_model.id,
             "title": post_model.title,
             "slug": post_model.slug,
@@ -111,47 +170,45 @@ pub async fn list_posts(
             "categories": categories,
             "created_at": post_model.created_at,
             "updated_at": post_model.updated_at,
-        }));
+        });
+        if full_text {
+            item["snippet"] = json!(snippets.remove(&post_model.id));
+        }
+        results.push(item);
     }
 
-    Ok(api_response(results))
+    api_response_paged(results, meta)
 }
 
 #[axum::debug_handler]
 pub async fn create_post(
     _state: State<Arc<AppState>>,
-    Json(form): Json<PostReq>,
-) -> Result<impl IntoResponse, (StatusCode, String)> {
+    auth: AuthUser,
+    ValidatedJson(form): ValidatedJson<PostReq>,
+) -> Result<impl IntoResponse, AppError> {
     let db = &_state.database_connection;
     let slug = slugify(&form.title);
-    let user = entity::user::Entity::find()
-        .filter(entity::user::Column::Username.eq(form.uploader.clone()))
-        .one(&_state.database_connection)
-        .await
-        .map_err(internal_error)?
-        .ok_or_else(|| (StatusCode::BAD_REQUEST, format!("User '{}' not found", form.uploader)))?;
 
-    let txn = db.begin().await.map_err(internal_error)?;
+    let txn = db.begin().await?;
 
     // Insert post
     let new_post = post::ActiveModel {
         title: Set(form.title.clone()),
         text: Set(form.text.clone()),
         slug: Set(slug),
-        user_id: Set(user.id.clone()),
+        user_id: Set(auth.id),
         ..Default::default()
     };
     info!("{:?}", form.categories.clone());
 
-    let inserted_post = new_post.insert(&txn).await.map_err(internal_error)?;
+    let inserted_post = new_post.insert(&txn).await?;
 
     // Insert relasi ke kategori
     for name in &form.categories {
         if let Some(cat) = category::Entity::find()
             .filter(category::Column::Name.eq(name))
             .one(&txn)
-            .await
-            .map_err(internal_error)?
+            .await?
         {
             let rel = post_category::ActiveModel {
                 post_id: Set(inserted_post.id.to_owned()),
@@ -161,16 +218,16 @@ pub async fn create_post(
             // Jika insert relasi gagal, rollback
             if let Err(e) = rel.insert(&txn).await {
                 txn.rollback().await.ok(); // best effort rollback
-                return Err(internal_error(format!("Gagal menghubungkan kategori '{}': {}", name, e)));
+                return Err(AppError::internal(format!("Gagal menghubungkan kategori '{}': {}", name, e)));
             }
         }
         // Jika kategori tidak ditemukan → skip
     }
 
     // Commit
-    txn.commit().await.map_err(internal_error)?;
+    txn.commit().await?;
 
-    Ok((StatusCode::CREATED, api_response_single(inserted_post)))
+    Ok((StatusCode::CREATED, api_response_single(inserted_post)?))
     // let post = ActiveModel {
     //     title: Set(form.title.to_owned()),
     //     text: Set(form.text.to_owned()),
@@ -187,61 +244,67 @@ pub async fn create_post(
     //             inserted.id.parse().unwrap(),
     //             form.categories.clone(),
     //         ).await {
-    //             return Err(internal_error(err));
+    //             return Err(err.into());
     //         }
     //
     //         Ok((StatusCode::CREATED, api_response_single(inserted)))
     //     },
-    //     Err(error) => Err(internal_error(error)),
+    //     Err(error) => Err(error.into()),
     // }
 }
 
 #[axum::debug_handler]
 pub async fn update_post(
     _state: State<Arc<AppState>>,
+    auth: AuthUser,
     Path(slug): Path<String>,
-    Json(form): Json<PostReq>,
-) -> Result<impl IntoResponse, (StatusCode, String)> {
+    ValidatedJson(form): ValidatedJson<PostReq>,
+) -> Result<impl IntoResponse, AppError> {
     match Post::find()
         .filter(Column::Slug.eq(slug))
         .one(&_state.database_connection).await {
         Ok(Some(old_post)) => {
+            ensure_owner(&auth, Some(old_post.user_id))?;
+
             let mut post = old_post.into_active_model();
             post.title = Set(form.title.clone());
             post.text = Set(form.text.clone());
 
             match post.update(&_state.database_connection).await {
-                Ok(updated) => Ok(api_response_single(updated)),
-                Err(error) => Err(internal_error(error)),
+                Ok(updated) => api_response_single(updated),
+                Err(error) => Err(error.into()),
             }
         }
-        Ok(None) => Err(not_found_error("No record yet.")),
-        Err(error) => Err(internal_error(error)),
+        Ok(None) => Err(AppError::not_found("No record yet.")),
+        Err(error) => Err(error.into()),
     }
 }
 
 #[axum::debug_handler]
 pub async fn delete_post(
     _state: State<Arc<AppState>>,
+    auth: AuthUser,
     Path(slug): Path<String>,
-) -> Result<impl IntoResponse, (StatusCode, String)> {
+) -> Result<impl IntoResponse, AppError> {
     match Post::find()
         .filter(Column::Slug.eq(slug))
         .one(&_state.database_connection).await {
         Ok(Some(post)) => {
+            ensure_owner(&auth, Some(post.user_id))?;
+
             match post
                 .into_active_model()
                 .delete(&_state.database_connection)
                 .await
             {
-                Ok(_) => Ok(api_response_single(
+                Ok(_) => api_response_single(
                     json!({ "message": "Post deleted successfully" }),
-                )),
-                Err(error) => Err(internal_error(error)),
+                ),
+                Err(error) => Err(error.into()),
             }
         }
-        Ok(None) => Err(not_found_error("Post not found")),
-        Err(error) => Err(internal_error(error)),
+        Ok(None) => Err(AppError::not_found("Post not found")),
+        Err(error) => Err(error.into()),
     }
 }
 
@@ -249,13 +312,82 @@ pub async fn delete_post(
 pub async fn get_post(
     _state: State<Arc<AppState>>,
     Path(slug): Path<String>,
-) -> Result<impl IntoResponse, (StatusCode, String)> {
-    match Post::find()
+) -> Result<impl IntoResponse, AppError> {
+    let post = Post::find()
         .filter(Column::Slug.eq(slug.as_str()))
-        .one(&_state.database_connection).await {
-        Ok(post) => Ok((StatusCode::OK, api_response_single(post))),
-        Err(error) => Err(internal_error(error)),
+        .one(&_state.database_connection)
+        .await?
+        .ok_or_else(|| AppError::not_found("Post not found"))?;
+
+    Ok((StatusCode::OK, api_response_single(post)?))
+}
+
+/// A stored file to attach to a post
+pub struct NewAttachment {
+    pub file_name: String,
+    /// Path under `storage`, with a leading `/`
+    pub file_path: String,
+    pub size: u64,
+    pub hash: String,
+}
+
+/// Post `slug` if `auth` may change it
+pub async fn find_owned_post(
+    db: &DatabaseConnection,
+    auth: &AuthUser,
+    slug: &str,
+) -> Result<post::Model, AppError> {
+    let post = Post::find()
+        .filter(Column::Slug.eq(slug))
+        .one(db)
+        .await?
+        .ok_or_else(|| AppError::not_found("Post not found"))?;
+
+    ensure_owner(auth, Some(post.user_id))?;
+    Ok(post)
+}
+
+pub async fn attach_file_to_post(
+    db: &DatabaseConnection,
+    post_id: Uuid,
+    user_id: Uuid,
+    attachment: NewAttachment,
+) -> Result<post_attachment::Model, AppError> {
+    let attachment = post_attachment::ActiveModel {
+        id: Set(Uuid::new_v4()),
+        post_id: Set(post_id),
+        user_id: Set(user_id),
+        file_name: Set(attachment.file_name),
+        file_path: Set(attachment.file_path),
+        size: Set(i64::try_from(attachment.size).map_err(anyhow::Error::from)?),
+        hash: Set(attachment.hash),
+        created_at: Set(Utc::now()),
     }
+    .insert(db)
+    .await?;
+
+    Ok(attachment)
+}
+
+#[axum::debug_handler]
+pub async fn list_post_attachments(
+    _state: State<Arc<AppState>>,
+    Path(slug): Path<String>,
+) -> Result<impl IntoResponse, AppError> {
+    let db = &_state.database_connection;
+    let post = Post::find()
+        .filter(Column::Slug.eq(slug))
+        .one(db)
+        .await?
+        .ok_or_else(|| AppError::not_found("Post not found"))?;
+
+    let attachments = post_attachment::Entity::find()
+        .filter(post_attachment::Column::PostId.eq(post.id))
+        .order_by_asc(post_attachment::Column::CreatedAt)
+        .all(db)
+        .await?;
+
+    api_response(attachments)
 }
 
 // pub async fn attach_categories_to_post(
@@ -0,0 +1,204 @@
+use crate::app::auth::extractor::AuthUser;
+use crate::app::auth::permission::Permission;
+use crate::error::AppError;
+use crate::respons::api_response_single;
+use crate::search::{any_contains, full_text_available, match_rank, post_matches, post_rank, post_snippets};
+use crate::utils::AppState;
+use crate::validation::{Validate, ValidatedQuery, Validator};
+use axum::extract::State;
+use axum::response::IntoResponse;
+use entity::{book, category, post, user};
+use sea_orm::{DatabaseConnection, EntityTrait, Order, QueryFilter, QueryOrder, QuerySelect};
+use serde::Deserialize;
+use serde_json::{json, Value};
+use std::collections::HashMap;
+use std::sync::Arc;
+
+const DEFAULT_LIMIT: u64 = 5;
+const MAX_LIMIT: u64 = 20;
+
+#[derive(Debug, Deserialize)]
+pub struct SearchQuery {
+    q: Option<String>,
+    /// Results per type, unless the type has its own limit below
+    limit: Option<u64>,
+    posts_limit: Option<u64>,
+    books_limit: Option<u64>,
+    categories_limit: Option<u64>,
+    users_limit: Option<u64>,
+}
+
+impl SearchQuery {
+    fn limit_of(&self, group: Option<u64>) -> u64 {
+        group.or(self.limit).unwrap_or(DEFAULT_LIMIT)
+    }
+}
+
+impl Validate for SearchQuery {
+    fn validate(&self) -> Result<(), AppError> {
+        let mut v = Validator::new();
+        match &self.q {
+            Some(q) => v.length("q", q.trim(), 1, 100),
+            None => v.check("q", false, "is required"),
+        };
+        let limits = [
+            ("limit", self.limit),
+            ("posts_limit", self.posts_limit),
+            ("books_limit", self.books_limit),
+            ("categories_limit", self.categories_limit),
+            ("users_limit", self.users_limit),
+        ];
+        for (field, limit) in limits {
+            if let Some(limit) = limit {
+                v.range(field, limit, 1, MAX_LIMIT);
+            }
+        }
+        v.finish()
+    }
+}
+
+/// One search box for the whole app: the best posts, books, categories and
+/// users for `q`, each group ranked and capped at its own limit. Users are
+/// only searched for callers who may list users, like on `/users`.
+#[axum::debug_handler]
+pub async fn search(
+    state: State<Arc<AppState>>,
+    auth: Option<AuthUser>,
+    ValidatedQuery(params): ValidatedQuery<SearchQuery>,
+) -> Result<impl IntoResponse, AppError> {
+    let db = &state.database_connection;
+    let q = params.q.clone().unwrap_or_default();
+    let q = q.trim();
+    let may_list_users = auth.is_some_and(|auth| auth.has(Permission::ListUsers));
+
+    let (posts, books, categories, users) = tokio::try_join!(
+        search_posts(db, q, params.limit_of(params.posts_limit)),
+        search_books(db, q, params.limit_of(params.books_limit)),
+        search_categories(db, q, params.limit_of(params.categories_limit)),
+        async {
+            if may_list_users {
+                search_users(db, q, params.limit_of(params.users_limit)).await.map(Some)
+            } else {
+                Ok(None)
+            }
+        },
+    )?;
+
+    let mut results = json!({
+        "posts": posts,
+        "books": books,
+        "categories": categories,
+    });
+    if let Some(users) = users {
+        results["users"] = json!(users);
+    }
+    api_response_single(results)
+}
+
+/// Full-text ranked with snippets on PostgreSQL, title/text LIKE elsewhere
+async fn search_posts(db: &DatabaseConnection, q: &str, limit: u64) -> Result<Vec<Value>, AppError> {
+    let ranked = full_text_available(db);
+    let query = if ranked {
+        post::Entity::find()
+            .filter(post_matches(q))
+            .order_by(post_rank(q), Order::Desc)
+    } else {
+        post::Entity::find()
+            .filter(any_contains(&[post::Column::Title, post::Column::Text], q))
+            .order_by_asc(match_rank(post::Column::Title, q))
+    };
+
+    let posts = query
+        .order_by_desc(post::Column::CreatedAt)
+        .limit(limit)
+        .all(db)
+        .await?;
+
+    let mut snippets = if ranked {
+        let ids = posts.iter().map(|post| post.id).collect::<Vec<_>>();
+        post_snippets(db, q, &ids).await?
+    } else {
+        HashMap::new()
+    };
+
+    Ok(posts
+        .into_iter()
+        .map(|post| {
+            json!({
+                "id": post.id,
+                "title": post.title,
+                "slug": post.slug,
+                "snippet": snippets.remove(&post.id),
+                "created_at": post.created_at,
+            })
+        })
+        .collect())
+}
+
+async fn search_books(db: &DatabaseConnection, q: &str, limit: u64) -> Result<Vec<Value>, AppError> {
+    let books = book::Entity::find()
+        .filter(any_contains(
+            &[book::Column::Title, book::Column::Writer, book::Column::Publisher],
+            q,
+        ))
+        .order_by_asc(match_rank(book::Column::Title, q))
+        .order_by_desc(book::Column::CreatedAt)
+        .limit(limit)
+        .all(db)
+        .await?;
+
+    Ok(books
+        .into_iter()
+        .map(|book| {
+            json!({
+                "title": book.title,
+                "writer": book.writer,
+                "publisher": book.publisher,
+                "created_at": book.created_at,
+            })
+        })
+        .collect())
+}
+
+async fn search_categories(db: &DatabaseConnection, q: &str, limit: u64) -> Result<Vec<Value>, AppError> {
+    let categories = category::Entity::find()
+        .filter(any_contains(&[category::Column::Name], q))
+        .order_by_asc(match_rank(category::Column::Name, q))
+        .order_by_asc(category::Column::Name)
+        .limit(limit)
+        .all(db)
+        .await?;
+
+    Ok(categories
+        .into_iter()
+        .map(|category| {
+            json!({
+                "id": category.id,
+                "name": category.name,
+                "slug": category.slug,
+            })
+        })
+        .collect())
+}
+
+/// Only what posts already show about their authors
+async fn search_users(db: &DatabaseConnection, q: &str, limit: u64) -> Result<Vec<Value>, AppError> {
+    let users = user::Entity::find()
+        .filter(any_contains(&[user::Column::Username, user::Column::Name], q))
+        .order_by_asc(match_rank(user::Column::Username, q))
+        .order_by_asc(user::Column::Username)
+        .limit(limit)
+        .all(db)
+        .await?;
+
+    Ok(users
+        .into_iter()
+        .map(|user| {
+            json!({
+                "username": user.username,
+                "name": user.name,
+                "profile_picture": user.profile_picture,
+            })
+        })
+        .collect())
+}
@@ -0,0 +1,315 @@
+use crate::app::auth::extractor::AuthUser;
+use crate::app::files::tus::{TusUpload, UploadLock, TUS_EXTENSIONS, TUS_VERSION};
+use crate::app::files::policy::{UploadKind, UploadPolicy};
+use crate::app::hashing::hash::{file_hash_algorithm, Checksum};
+use crate::controllers::book_controller::{store_book, NewBook};
+use crate::controllers::post_controller::{attach_file_to_post, find_owned_post, NewAttachment};
+use crate::controllers::user_controller::set_profile_picture;
+use crate::error::AppError;
+use crate::utils::AppState;
+use axum::body::Body;
+use axum::extract::{OriginalUri, Path, State};
+use axum::http::header::{CACHE_CONTROL, CONTENT_TYPE, LOCATION};
+use axum::http::{HeaderMap, StatusCode};
+use axum::response::{IntoResponse, Response};
+use sea_orm::DatabaseConnection;
+use std::collections::HashMap;
+use std::sync::Arc;
+use tracing::info;
+use uuid::Uuid;
+
+/// What a finished upload becomes, chosen with the `kind` metadata
+enum Target {
+    /// Stored under `uploads/files`
+    File,
+    /// Stored under `uploads/avatars`
+    Avatar,
+    /// Needs `title`, `writer`, `publisher` and optionally `categories` as a JSON array
+    Book { categories: Vec<String> },
+    /// Needs `post`, the slug of a post the uploader may change
+    PostAttachment { slug: String },
+}
+
+impl Target {
+    fn from_metadata(metadata: &HashMap<String, String>) -> Result<Self, AppError> {
+        let kind = match metadata.get("kind") {
+            None => UploadKind::File,
+            Some(name) => UploadKind::parse(name)
+                .ok_or_else(|| AppError::bad_request(format!("Unknown upload kind '{}'", name)))?,
+        };
+
+        match kind {
+            UploadKind::File => Ok(Self::File),
+            UploadKind::Avatar => Ok(Self::Avatar),
+            UploadKind::Book => {
+                for field in ["title", "writer", "publisher"] {
+                    if metadata.get(field).is_none_or(|value| value.trim().is_empty()) {
+                        return Err(AppError::bad_request(format!(
+                            "Book uploads need a {} in Upload-Metadata",
+                            field
+                        )));
+                    }
+                }
+                let categories = match metadata.get("categories") {
+                    None => Vec::new(),
+                    Some(raw) => serde_json::from_str(raw).map_err(|_| {
+                        AppError::bad_request("categories in Upload-Metadata must be a JSON array of names")
+                    })?,
+                };
+                Ok(Self::Book { categories })
+            }
+            UploadKind::PostAttachment => metadata
+                .get("post")
+                .map(|slug| Self::PostAttachment { slug: slug.clone() })
+                .ok_or_else(|| AppError::bad_request("Post attachments need a post in Upload-Metadata")),
+        }
+    }
+
+    fn kind(&self) -> UploadKind {
+        match self {
+            Self::File => UploadKind::File,
+            Self::Avatar => UploadKind::Avatar,
+            Self::Book { .. } => UploadKind::Book,
+            Self::PostAttachment { .. } => UploadKind::PostAttachment,
+        }
+    }
+
+    fn policy(&self) -> UploadPolicy {
+        UploadPolicy::for_kind(self.kind())
+    }
+}
+
+/// `Tus-Max-Size`, the largest upload any kind allows
+fn max_upload_size() -> u64 {
+    UploadKind::ALL
+        .into_iter()
+        .map(|kind| UploadPolicy::for_kind(kind).max_size)
+        .max()
+        .unwrap_or_default()
+}
+
+fn header<'a>(headers: &'a HeaderMap, name: &str) -> Option<&'a str> {
+    headers.get(name).and_then(|value| value.to_str().ok())
+}
+
+fn number_header(headers: &HeaderMap, name: &str) -> Result<u64, AppError> {
+    header(headers, name)
+        .ok_or_else(|| AppError::bad_request(format!("Missing {} header", name)))?
+        .parse()
+        .map_err(|_| AppError::bad_request(format!("{} must be a non-negative integer", name)))
+}
+
+/// Server capabilities
+pub async fn tus_options() -> impl IntoResponse {
+    (
+        StatusCode::NO_CONTENT,
+        [
+            ("tus-version", TUS_VERSION.to_string()),
+            ("tus-extension", TUS_EXTENSIONS.to_string()),
+            ("tus-max-size", max_upload_size().to_string()),
+            ("tus-checksum-algorithm", file_hash_algorithm().to_string()),
+        ],
+    )
+}
+
+/// Creation extension: `Upload-Length` and `Upload-Metadata` in, `Location` out
+#[axum::debug_handler]
+pub async fn tus_create(
+    state: State<Arc<AppState>>,
+    auth: AuthUser,
+    OriginalUri(uri): OriginalUri,
+    headers: HeaderMap,
+) -> Result<Response, AppError> {
+    if headers.contains_key("upload-defer-length") {
+        return Err(AppError::bad_request("Upload-Defer-Length is not supported"));
+    }
+    let length = number_header(&headers, "upload-length")?;
+    if length > max_upload_size() {
+        return Err(AppError::PayloadTooLarge(format!("Upload-Length is over {} bytes", max_upload_size())));
+    }
+
+    let upload = TusUpload::create(auth.id, length, header(&headers, "upload-metadata").map(str::to_string)).await?;
+
+    // Refuse now rather than after the whole file arrived
+    let checked = match Target::from_metadata(&upload.metadata) {
+        Ok(target) => check_target(&state.database_connection, &auth, &upload, &target).await,
+        Err(err) => Err(err),
+    };
+    if let Err(err) = checked {
+        upload.discard().await.ok();
+        return Err(err);
+    }
+
+    let location = format!("{}/{}", uri.path().trim_end_matches('/'), upload.id);
+    Ok((
+        StatusCode::CREATED,
+        [(LOCATION.as_str(), location), ("upload-expires", upload.expires().await?)],
+    )
+        .into_response())
+}
+
+/// How much of the upload the server has
+#[axum::debug_handler]
+pub async fn tus_head(
+    _state: State<Arc<AppState>>,
+    auth: AuthUser,
+    Path(id): Path<Uuid>,
+) -> Result<Response, AppError> {
+    let upload = TusUpload::load(id, auth.id).await?;
+    let offset = upload.offset().await?;
+
+    let mut response = (
+        StatusCode::OK,
+        [
+            ("upload-offset", offset.to_string()),
+            ("upload-length", upload.length.to_string()),
+            ("upload-expires", upload.expires().await?),
+            (CACHE_CONTROL.as_str(), "no-store".to_string()),
+        ],
+    )
+        .into_response();
+    if let Some(metadata) = upload.raw_metadata.and_then(|raw| raw.parse().ok()) {
+        response.headers_mut().insert("upload-metadata", metadata);
+    }
+    Ok(response)
+}
+
+/// Append the body at `Upload-Offset`, the last PATCH hands the file on and
+/// answers with where it is stored in `Upload-File`
+#[axum::debug_handler]
+pub async fn tus_patch(
+    state: State<Arc<AppState>>,
+    auth: AuthUser,
+    Path(id): Path<Uuid>,
+    headers: HeaderMap,
+    body: Body,
+) -> Result<Response, AppError> {
+    if header(&headers, CONTENT_TYPE.as_str()) != Some("application/offset+octet-stream") {
+        return Err(AppError::UnsupportedMediaType(
+            "Content-Type must be application/offset+octet-stream".to_string(),
+        ));
+    }
+    let offset = number_header(&headers, "upload-offset")?;
+
+    let _lock = UploadLock::acquire(id)?;
+    let upload = TusUpload::load(id, auth.id).await?;
+
+    let checksum = header(&headers, "upload-checksum").map(Checksum::parse).transpose()?;
+    let policy = Target::from_metadata(&upload.metadata)?.policy();
+
+    let new_offset = upload
+        .append(offset, body.into_data_stream(), checksum, |head| {
+            // Only the start of the file carries its signature
+            if offset == 0 {
+                policy.check_content(&upload.file_name(), head)?;
+            }
+            Ok(())
+        })
+        .await?;
+    if new_offset == upload.length {
+        let stored = hand_off(&state.database_connection, &auth, &upload).await?;
+        return Ok((
+            StatusCode::NO_CONTENT,
+            [("upload-offset", new_offset.to_string()), ("upload-file", stored)],
+        )
+            .into_response());
+    }
+
+    Ok((
+        StatusCode::NO_CONTENT,
+        [("upload-offset", new_offset.to_string()), ("upload-expires", upload.expires().await?)],
+    )
+        .into_response())
+}
+
+/// Termination extension
+#[axum::debug_handler]
+pub async fn tus_delete(
+    _state: State<Arc<AppState>>,
+    auth: AuthUser,
+    Path(id): Path<Uuid>,
+) -> Result<Response, AppError> {
+    let _lock = UploadLock::acquire(id)?;
+    TusUpload::load(id, auth.id).await?.discard().await?;
+
+    Ok(StatusCode::NO_CONTENT.into_response())
+}
+
+/// The upload policy of `target` and, for attachments, access to the post
+async fn check_target(
+    db: &DatabaseConnection,
+    auth: &AuthUser,
+    upload: &TusUpload,
+    target: &Target,
+) -> Result<(), AppError> {
+    let policy = target.policy();
+    policy.check_size(upload.length)?;
+    policy.check_file_name(&upload.file_name())?;
+
+    if let Target::PostAttachment { slug } = target {
+        find_owned_post(db, auth, slug).await?;
+    }
+    Ok(())
+}
+
+/// Store the finished file where its `kind` belongs and create the book or
+/// attachment. The upload is only forgotten once that worked; otherwise the
+/// file goes back to the upload and an empty PATCH at the end retries.
+/// Avatars become the uploader's profile picture. Returns the stored path
+/// under `storage`, with a leading `/`.
+async fn hand_off(db: &DatabaseConnection, auth: &AuthUser, upload: &TusUpload) -> Result<String, AppError> {
+    let meta = |key: &str| upload.metadata.get(key).cloned().unwrap_or_default();
+    let target = Target::from_metadata(&upload.metadata)?;
+    let dir = target.kind().dir();
+
+    let stored = match target {
+        Target::File => {
+            let (path, hash, _) = upload.finish(dir).await?;
+            info!("tus upload {} by {} stored at {}, Hash: {}", upload.id, auth.username, path, hash);
+            path
+        }
+        Target::Avatar => {
+            let (path, hash, _) = upload.finish(dir).await?;
+            if let Err(err) = set_profile_picture(db, auth.id, format!("/{}", path)).await {
+                upload.restore(&path).await?;
+                return Err(err);
+            }
+            info!("Avatar uploaded by {}: {}, Hash: {}", auth.username, path, hash);
+            path
+        }
+        Target::Book { categories } => {
+            let (path, hash, _) = upload.finish(dir).await?;
+            let new_book = NewBook {
+                title: meta("title"),
+                writer: meta("writer"),
+                publisher: meta("publisher"),
+                book_file: format!("/{}", path),
+                categories,
+            };
+            if let Err(err) = store_book(db, auth.id, new_book).await {
+                upload.restore(&path).await?;
+                return Err(err);
+            }
+            info!("Book uploaded by {}: {}, Hash: {}", auth.username, path, hash);
+            path
+        }
+        Target::PostAttachment { slug } => {
+            let post = find_owned_post(db, auth, &slug).await?;
+            let (path, hash, size) = upload.finish(dir).await?;
+            let attachment = NewAttachment {
+                file_name: upload.file_name(),
+                file_path: format!("/{}", path),
+                size,
+                hash,
+            };
+            if let Err(err) = attach_file_to_post(db, post.id, auth.id, attachment).await {
+                upload.restore(&path).await?;
+                return Err(err);
+            }
+            info!("Attachment uploaded by {} to post {}: {}", auth.username, slug, path);
+            path
+        }
+    };
+    upload.forget().await;
+    Ok(format!("/{}", stored))
+}
@@ -0,0 +1,128 @@
+use crate::app::auth::extractor::AuthUser;
+use crate::app::files::files::{write_stream, TempFile};
+use crate::app::files::policy::UploadKind;
+use crate::app::files::session::UploadSession;
+use crate::app::hashing::hash::{Checksum, FileHasher};
+use crate::error::AppError;
+use crate::respons::api_response_single;
+use crate::utils::AppState;
+use crate::validation::{Validate, ValidatedJson, Validator};
+use axum::body::Body;
+use axum::extract::{Path, State};
+use axum::http::{HeaderMap, StatusCode};
+use axum::response::IntoResponse;
+use serde::Deserialize;
+use serde_json::json;
+use std::sync::Arc;
+use tracing::info;
+use uuid::Uuid;
+
+const MAX_CHUNKS: usize = 10_000;
+/// Books and post attachments are uploaded through their own endpoints
+const SESSION_KINDS: &[&str] = &["file", "avatar"];
+
+#[derive(Debug, Deserialize)]
+pub struct InitUploadReq {
+    file_name: String,
+    total_chunks: usize,
+    /// `file` unless given
+    kind: Option<String>,
+}
+
+impl Validate for InitUploadReq {
+    fn validate(&self) -> Result<(), AppError> {
+        let mut v = Validator::new();
+        v.length("file_name", &self.file_name, 1, 255)
+            .range("total_chunks", self.total_chunks, 1, MAX_CHUNKS);
+        if let Some(kind) = &self.kind {
+            v.one_of("kind", kind, SESSION_KINDS);
+        }
+        v.finish()
+    }
+}
+
+/// Start an upload, the returned `id` is used for every chunk
+#[axum::debug_handler]
+pub async fn init_upload(
+    _state: State<Arc<AppState>>,
+    auth: AuthUser,
+    ValidatedJson(form): ValidatedJson<InitUploadReq>,
+) -> Result<impl IntoResponse, AppError> {
+    let kind = form.kind.as_deref().and_then(UploadKind::parse).unwrap_or_default();
+    let session = UploadSession::create(auth.id, kind, &form.file_name, form.total_chunks).await?;
+
+    Ok((StatusCode::CREATED, api_response_single(session.status().await?)?))
+}
+
+/// Which chunks arrived, so a client can resend only the missing ones
+#[axum::debug_handler]
+pub async fn upload_status(
+    _state: State<Arc<AppState>>,
+    auth: AuthUser,
+    Path(id): Path<Uuid>,
+) -> Result<impl IntoResponse, AppError> {
+    let session = UploadSession::load(id, auth.id).await?;
+
+    api_response_single(session.status().await?)
+}
+
+/// The request body is the raw chunk, streamed to disk as it arrives. An
+/// optional `Upload-Checksum: <algorithm> <base64 digest>` is checked before
+/// the chunk is stored.
+#[axum::debug_handler]
+pub async fn put_chunk(
+    _state: State<Arc<AppState>>,
+    auth: AuthUser,
+    Path((id, index)): Path<(Uuid, usize)>,
+    headers: HeaderMap,
+    body: Body,
+) -> Result<impl IntoResponse, AppError> {
+    let checksum = headers
+        .get("upload-checksum")
+        .map(|value| value.to_str().map_err(|_| AppError::bad_request("Invalid Upload-Checksum")))
+        .transpose()?
+        .map(Checksum::parse)
+        .transpose()?;
+    let session = UploadSession::load(id, auth.id).await?;
+
+    let mut chunk = TempFile::create().await?;
+    let mut hasher = FileHasher::new();
+    let written = write_stream(body.into_data_stream(), &mut chunk.file, session.policy().chunk_size, |bytes| {
+        hasher.update(bytes)
+    })
+    .await?;
+    if written == 0 {
+        return Err(AppError::bad_request("Chunk is empty"));
+    }
+    session.store_chunk(index, chunk, &hasher.finalize(), checksum.as_ref()).await?;
+
+    api_response_single(session.status().await?)
+}
+
+#[axum::debug_handler]
+pub async fn complete_upload(
+    _state: State<Arc<AppState>>,
+    auth: AuthUser,
+    Path(id): Path<Uuid>,
+) -> Result<impl IntoResponse, AppError> {
+    let session = UploadSession::load(id, auth.id).await?;
+    let (path, hash) = session.complete().await?;
+    session.forget().await;
+
+    info!("Upload {} completed by {}: {}", id, auth.username, path);
+    api_response_single(json!({
+        "file": format!("/{}", path),
+        "hash": hash,
+    }))
+}
+
+#[axum::debug_handler]
+pub async fn cancel_upload(
+    _state: State<Arc<AppState>>,
+    auth: AuthUser,
+    Path(id): Path<Uuid>,
+) -> Result<impl IntoResponse, AppError> {
+    UploadSession::load(id, auth.id).await?.discard().await?;
+
+    Ok(StatusCode::NO_CONTENT)
+}
@@ -1,17 +1,29 @@
-use crate::app::hashing::hash::{hash_password, verify_password};
-use crate::respons::{api_response, api_response_single};
-use crate::routes::{internal_error, not_found_error};
+use crate::app::auth::extractor::AuthUser;
+use crate::app::auth::jwt::issue_mfa_token;
+use crate::app::auth::permission::Role;
+use crate::app::auth::throttle::client_ip;
+use crate::app::auth::session::{revoke_user_sessions, start_session};
+use crate::app::hashing::hash::{hash_password, needs_rehash, verify_password};
+use crate::controllers::auth_controller::login_payload;
+use crate::dto::PublicUser;
+use crate::error::AppError;
+use crate::pagination::{validate_page_params, PageMeta, Pagination};
+use crate::respons::{api_response_paged, api_response_single};
 use crate::utils::AppState;
-use axum::extract::State;
-use axum::http::StatusCode;
+use crate::validation::{Validate, ValidatedJson, ValidatedQuery, Validator, USERNAME_PATTERN};
+use axum::extract::{ConnectInfo, OriginalUri, Path, State};
+use axum::http::{HeaderMap, StatusCode};
 use axum::response::IntoResponse;
-use axum::Json;
 use entity::user;
 use entity::user::ActiveModel;
-use sea_orm::{ActiveModelTrait, ColumnTrait, EntityOrSelect, EntityTrait, ModelTrait, QueryFilter, Set};
+use sea_orm::sea_query::Expr;
+use sea_orm::{ActiveModelTrait, ColumnTrait, DatabaseConnection, EntityOrSelect, EntityTrait, IntoActiveModel, PaginatorTrait, QueryFilter, QueryOrder, QuerySelect, Set, SqlErr};
 use serde::Deserialize;
 use serde_json::json;
+use std::net::SocketAddr;
 use std::sync::Arc;
+use tracing::{info, warn};
+use uuid::Uuid;
 
 #[derive(Debug, Deserialize)]
 pub struct UserRequest {
@@ -20,40 +32,106 @@ pub struct UserRequest {
     name: String,
 }
 
+impl Validate for UserRequest {
+    fn validate(&self) -> Result<(), AppError> {
+        Validator::new()
+            .length("username", &self.username, 3, 32)
+            .pattern("username", &self.username, &USERNAME_PATTERN, "letters, digits, '_', '.' and '-'")
+            .length("password", &self.password, 8, 128)
+            .length("name", self.name.trim(), 1, 100)
+            .finish()
+    }
+}
+
+#[derive(Deserialize)]
+pub struct UserQuery {
+    page: Option<u64>,
+    limit: Option<u64>,
+}
+
+impl Validate for UserQuery {
+    fn validate(&self) -> Result<(), AppError> {
+        let mut v = Validator::new();
+        validate_page_params(&mut v, self.page, self.limit, None);
+        v.finish()
+    }
+}
+
 #[derive(Deserialize)]
 pub struct UserLogin {
     username: String,
     password: String
 }
 
+impl Validate for UserLogin {
+    fn validate(&self) -> Result<(), AppError> {
+        Validator::new()
+            .length("username", &self.username, 1, 32)
+            .length("password", &self.password, 1, 128)
+            .finish()
+    }
+}
+
+#[derive(Deserialize)]
+pub struct RoleRequest {
+    role: Role,
+}
+
+/// `role` is checked while deserializing
+impl Validate for RoleRequest {
+    fn validate(&self) -> Result<(), AppError> {
+        Ok(())
+    }
+}
+
+#[derive(Deserialize)]
+pub struct ChangePasswordRequest {
+    old_password: String,
+    new_password: String,
+}
+
+impl Validate for ChangePasswordRequest {
+    fn validate(&self) -> Result<(), AppError> {
+        Validator::new()
+            .length("old_password", &self.old_password, 1, 128)
+            .length("new_password", &self.new_password, 8, 128)
+            .finish()
+    }
+}
+
 #[axum::debug_handler]
-pub async fn list_users(state: State<Arc<AppState>>) -> impl IntoResponse {
+pub async fn list_users(
+    state: State<Arc<AppState>>,
+    OriginalUri(uri): OriginalUri,
+    ValidatedQuery(params): ValidatedQuery<UserQuery>,
+) -> Result<impl IntoResponse, AppError> {
+    let pagination = Pagination::new(params.page, params.limit);
+    let total = user::Entity
+        .select()
+        .count(&state.database_connection)
+        .await?;
     let users = user::Entity
         .select()
+        .order_by_asc(user::Column::CreatedAt)
+        .offset(pagination.offset())
+        .limit(pagination.limit)
         .all(&state.database_connection)
-        .await
-        .unwrap();
+        .await?;
     let users = users
         .into_iter()
-        .map(|user| {
-           json!({
-                "id": user.id,
-                "name": user.name,
-               "username": user.username,
-               "password": user.password,
-           })
-        }).collect::<Vec<_>>();
-    
-    api_response(users)
+        .map(PublicUser::from)
+        .collect::<Vec<_>>();
+
+    api_response_paged(users, PageMeta::new(&uri, pagination, total))
 }
 
 #[axum::debug_handler]
 pub async fn create_user(
     state: State<Arc<AppState>>,
-    Json(user_form): Json<UserRequest>,
+    ValidatedJson(user_form): ValidatedJson<UserRequest>,
     
-) -> impl IntoResponse {
-    let password_hashing = hash_password(user_form.password.as_str()).unwrap();
+) -> Result<impl IntoResponse, AppError> {
+    let password_hashing = hash_password(user_form.password.as_str())?;
     let user = ActiveModel {
         name: Set(user_form.name.to_owned()),
         username: Set(user_form.username.to_owned()),
@@ -61,50 +139,165 @@ pub async fn create_user(
         ..Default::default()
     };
     
-    match user.insert(&state.database_connection).await { 
-        Ok(inserted_user) => Ok((StatusCode::CREATED, api_response_single(
-            vec![inserted_user].into_iter()
-                .map(|user| {
-                    json!({
-                        "id": user.id,
-                        "name": user.name,
-                       "username": user.username,
-                       "password": user.password,
-                        "created_at": user.created_at,
-                        "updated_at": user.updated_at
-                   })
-                }).collect::<Vec<_>>()
-        ))),
-        Err(err) => Err(internal_error(err))
+    let inserted_user = user
+        .insert(&state.database_connection)
+        .await
+        .map_err(|err| match err.sql_err() {
+            Some(SqlErr::UniqueConstraintViolation(_)) => AppError::conflict("Username is already taken"),
+            _ => AppError::from(err),
+        })?;
+
+    Ok((StatusCode::CREATED, api_response_single(PublicUser::from(inserted_user))?))
+}
+
+/// Re-hash a just verified password when `HASH_DRIVER` or its parameters changed.
+/// A failure here must not block the login, the old hash still works.
+async fn upgrade_password_hash(db: &DatabaseConnection, user_model: &user::Model, password: &str) {
+    match needs_rehash(&user_model.password) {
+        Ok(true) => {}
+        Ok(false) => return,
+        Err(err) => {
+            warn!("Could not inspect password hash of {}: {}", user_model.username, err);
+            return;
+        }
+    }
+
+    let new_hash = match hash_password(password) {
+        Ok(hash) => hash,
+        Err(err) => {
+            warn!("Could not rehash password of {}: {}", user_model.username, err);
+            return;
+        }
+    };
+
+    let mut user = user_model.clone().into_active_model();
+    user.password = Set(new_hash);
+    match user.update(db).await {
+        Ok(_) => info!("Upgraded password hash of {}", user_model.username),
+        Err(err) => warn!("Could not store rehashed password of {}: {}", user_model.username, err),
     }
 }
 
 #[axum::debug_handler]
 pub async fn get_user_credentials(
     state: State<Arc<AppState>>,
-    Json(user_form): Json<UserLogin>,
-) -> impl IntoResponse {
+    ConnectInfo(peer): ConnectInfo<SocketAddr>,
+    headers: HeaderMap,
+    ValidatedJson(user_form): ValidatedJson<UserLogin>,
+) -> Result<impl IntoResponse, AppError> {
+    let ip = client_ip(&headers, peer);
+    let throttle = &state.login_throttle;
+    throttle.begin(&user_form.username, &ip).await?;
+
     let user = entity::user::Entity::find()
-        .filter(user::Column::Username.eq(user_form.username))
+        .filter(user::Column::Username.eq(&user_form.username))
         .one(&state.database_connection)
         .await;
 
     match user {
         Ok(Some(user_model)) => {
             match verify_password(&user_model.password, &user_form.password) { 
-                Ok(true) => Ok((StatusCode::OK, api_response_single(
-                    json!({
-                        "id": user_model.id,
-                        "name": user_model.name,
-                        "username": user_model.username,
-                        "profile_picture": user_model.profile_picture
-                    })
-                ))),
-                Ok(false) => Err(internal_error("Password is incorrect")),
-                Err(err) => Err(internal_error(err))
+                Ok(true) => {
+                    upgrade_password_hash(&state.database_connection, &user_model, &user_form.password).await;
+
+                    // Enrolled users get a short-lived token for the second step instead of a session
+                    if user_model.totp_enabled {
+                        throttle.cancel(&user_form.username, &ip).await?;
+                        let (mfa_token, expires_in) = issue_mfa_token(&user_model)?;
+                        return Ok((StatusCode::OK, api_response_single(json!({
+                            "mfa_required": true,
+                            "mfa_token": mfa_token,
+                            "expires_in": expires_in
+                        }))?));
+                    }
+
+                    throttle.record_success(&user_form.username, &ip).await?;
+                    let tokens = start_session(&state.database_connection, &user_model).await?;
+                    Ok((StatusCode::OK, api_response_single(login_payload(user_model, tokens))?))
+                }
+                Ok(false) => Err(AppError::unauthorized("Invalid username or password")),
+                Err(err) => Err(err.into())
             }
         }
-        
-        _ => Err(not_found_error("User not found")),
+
+        // Unknown usernames count too, so guessing accounts is slowed down as well
+        Ok(None) => Err(AppError::unauthorized("Invalid username or password")),
+        Err(err) => Err(err.into()),
     }
-}
\ No newline at end of file
+}
+
+#[axum::debug_handler]
+pub async fn update_user_role(
+    state: State<Arc<AppState>>,
+    Path(id): Path<Uuid>,
+    ValidatedJson(form): ValidatedJson<RoleRequest>,
+) -> Result<impl IntoResponse, AppError> {
+    let user_model = user::Entity::find_by_id(id)
+        .one(&state.database_connection)
+        .await?
+        .ok_or_else(|| AppError::not_found("User not found"))?;
+
+    let mut user = user_model.into_active_model();
+    user.role = Set(form.role.as_str().to_string());
+    let updated = user.update(&state.database_connection).await?;
+    // Refresh tokens still carry the old role
+    revoke_user_sessions(&state.database_connection, id).await?;
+
+    Ok((StatusCode::OK, api_response_single(PublicUser::from(updated))?))
+}
+
+#[axum::debug_handler]
+pub async fn get_me(
+    state: State<Arc<AppState>>,
+    auth: AuthUser,
+) -> Result<impl IntoResponse, AppError> {
+    let user_model = user::Entity::find_by_id(auth.id)
+        .one(&state.database_connection)
+        .await?
+        .ok_or_else(|| AppError::not_found("User not found"))?;
+
+    Ok((StatusCode::OK, api_response_single(PublicUser::from(user_model))?))
+}
+
+/// Point the profile picture of `user_id` at a stored file, `path` is under
+/// `storage` with a leading `/`
+pub async fn set_profile_picture(db: &DatabaseConnection, user_id: Uuid, path: String) -> Result<(), AppError> {
+    let updated = user::Entity::update_many()
+        .col_expr(user::Column::ProfilePicture, Expr::value(path))
+        .col_expr(user::Column::UpdatedAt, Expr::value(chrono::Utc::now()))
+        .filter(user::Column::Id.eq(user_id))
+        .exec(db)
+        .await?;
+    if updated.rows_affected == 0 {
+        return Err(AppError::not_found("User not found"));
+    }
+    Ok(())
+}
+
+#[axum::debug_handler]
+pub async fn change_password(
+    state: State<Arc<AppState>>,
+    auth: AuthUser,
+    ValidatedJson(form): ValidatedJson<ChangePasswordRequest>,
+) -> Result<impl IntoResponse, AppError> {
+    auth.require_session("change the password")?;
+
+    let user_model = user::Entity::find_by_id(auth.id)
+        .one(&state.database_connection)
+        .await?
+        .ok_or_else(|| AppError::not_found("User not found"))?;
+
+    if !verify_password(&user_model.password, &form.old_password)? {
+        return Err(AppError::bad_request("Old password is incorrect"));
+    }
+
+    let mut user = user_model.into_active_model();
+    user.password = Set(hash_password(&form.new_password)?);
+    user.updated_at = Set(chrono::Utc::now());
+    user.update(&state.database_connection).await?;
+
+    // Other devices have to log in again with the new password
+    revoke_user_sessions(&state.database_connection, auth.id).await?;
+
+    Ok((StatusCode::OK, api_response_single(json!({ "message": "Password changed" }))?))
+}
@@ -0,0 +1,59 @@
+use crate::app::auth::api_key::{parse_scopes, Scope};
+use chrono::{DateTime, Utc};
+use entity::{api_key, user};
+use serde::Serialize;
+use uuid::Uuid;
+
+/// The user fields that are safe to send to clients, never the password hash
+#[derive(Debug, Serialize)]
+pub struct PublicUser {
+    pub id: Uuid,
+    pub name: String,
+    pub username: String,
+    pub profile_picture: Option<String>,
+    pub role: String,
+    pub two_factor_enabled: bool,
+    pub created_at: DateTime<Utc>,
+    pub updated_at: DateTime<Utc>,
+}
+
+impl From<user::Model> for PublicUser {
+    fn from(user: user::Model) -> Self {
+        Self {
+            id: user.id,
+            name: user.name,
+            username: user.username,
+            profile_picture: user.profile_picture,
+            role: user.role,
+            two_factor_enabled: user.totp_enabled,
+            created_at: user.created_at,
+            updated_at: user.updated_at,
+        }
+    }
+}
+
+/// An API key as listed to its owner, the key itself is only shown on creation
+#[derive(Debug, Serialize)]
+pub struct PublicApiKey {
+    pub id: Uuid,
+    pub name: String,
+    pub prefix: String,
+    pub scopes: Vec<Scope>,
+    pub expires_at: Option<DateTime<Utc>>,
+    pub last_used_at: Option<DateTime<Utc>>,
+    pub created_at: DateTime<Utc>,
+}
+
+impl From<api_key::Model> for PublicApiKey {
+    fn from(key: api_key::Model) -> Self {
+        Self {
+            id: key.id,
+            name: key.name,
+            prefix: key.prefix,
+            scopes: parse_scopes(&key.scopes),
+            expires_at: key.expires_at,
+            last_used_at: key.last_used_at,
+            created_at: key.created_at,
+        }
+    }
+}
@@ -0,0 +1,170 @@
+use crate::validation::FieldError;
+use axum::extract::multipart::MultipartError;
+use axum::http::header::RETRY_AFTER;
+use axum::http::StatusCode;
+use axum::response::{IntoResponse, Response};
+use axum::Json;
+use sea_orm::DbErr;
+use serde_json::json;
+use tracing::error;
+
+/// Every error a handler can return. Rendered as
+/// `{"error": "<code>", "message": "<text>"}` with the matching status,
+/// validation errors add `"fields": [{"field", "message"}]`.
+#[derive(Debug)]
+pub enum AppError {
+    BadRequest(String),
+    Unauthorized(String),
+    Forbidden(String),
+    NotFound(String),
+    Conflict(String),
+    PayloadTooLarge(String),
+    UnsupportedMediaType(String),
+    /// Uploaded bytes don't match the checksum the client sent, 460 as tus defines it
+    ChecksumMismatch(String),
+    TooManyRequests { message: String, retry_after: i64 },
+    /// One entry per rejected field
+    Validation(Vec<FieldError>),
+    Multipart(MultipartError),
+    /// Logged, never shown to the client
+    Database(DbErr),
+    /// Logged, never shown to the client
+    Internal(anyhow::Error),
+}
+
+impl AppError {
+    pub fn bad_request(msg: impl Into<String>) -> Self {
+        Self::BadRequest(msg.into())
+    }
+
+    pub fn unauthorized(msg: impl Into<String>) -> Self {
+        Self::Unauthorized(msg.into())
+    }
+
+    pub fn forbidden(msg: impl Into<String>) -> Self {
+        Self::Forbidden(msg.into())
+    }
+
+    pub fn not_found(msg: impl Into<String>) -> Self {
+        Self::NotFound(msg.into())
+    }
+
+    pub fn conflict(msg: impl Into<String>) -> Self {
+        Self::Conflict(msg.into())
+    }
+
+    pub fn internal(msg: impl Into<String>) -> Self {
+        Self::Internal(anyhow::anyhow!(msg.into()))
+    }
+
+    pub fn status(&self) -> StatusCode {
+        match self {
+            Self::BadRequest(_) => StatusCode::BAD_REQUEST,
+            Self::Unauthorized(_) => StatusCode::UNAUTHORIZED,
+            Self::Forbidden(_) => StatusCode::FORBIDDEN,
+            Self::NotFound(_) => StatusCode::NOT_FOUND,
+            Self::Conflict(_) => StatusCode::CONFLICT,
+            Self::PayloadTooLarge(_) => StatusCode::PAYLOAD_TOO_LARGE,
+            Self::UnsupportedMediaType(_) => StatusCode::UNSUPPORTED_MEDIA_TYPE,
+            Self::ChecksumMismatch(_) => StatusCode::from_u16(460).unwrap_or(StatusCode::BAD_REQUEST),
+            Self::TooManyRequests { .. } => StatusCode::TOO_MANY_REQUESTS,
+            Self::Validation(_) => StatusCode::BAD_REQUEST,
+            Self::Multipart(err) => err.status(),
+            Self::Database(DbErr::RecordNotFound(_)) => StatusCode::NOT_FOUND,
+            Self::Database(_) | Self::Internal(_) => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+
+    /// Machine-readable code clients can match on
+    pub fn code(&self) -> &'static str {
+        match self {
+            Self::BadRequest(_) => "bad_request",
+            Self::Unauthorized(_) => "unauthorized",
+            Self::Forbidden(_) => "forbidden",
+            Self::NotFound(_) => "not_found",
+            Self::Conflict(_) => "conflict",
+            Self::PayloadTooLarge(_) => "payload_too_large",
+            Self::UnsupportedMediaType(_) => "unsupported_media_type",
+            Self::ChecksumMismatch(_) => "checksum_mismatch",
+            Self::TooManyRequests { .. } => "too_many_requests",
+            Self::Validation(_) => "validation_failed",
+            Self::Multipart(_) => "invalid_multipart",
+            Self::Database(DbErr::RecordNotFound(_)) => "not_found",
+            Self::Database(_) => "database_error",
+            Self::Internal(_) => "internal_error",
+        }
+    }
+
+    fn message(&self) -> String {
+        match self {
+            Self::BadRequest(msg)
+            | Self::Unauthorized(msg)
+            | Self::Forbidden(msg)
+            | Self::NotFound(msg)
+            | Self::Conflict(msg)
+            | Self::PayloadTooLarge(msg)
+            | Self::UnsupportedMediaType(msg)
+            | Self::ChecksumMismatch(msg)
+            | Self::TooManyRequests { message: msg, .. } => msg.clone(),
+            Self::Validation(_) => "Some fields are invalid".to_string(),
+            Self::Multipart(err) => err.body_text(),
+            Self::Database(DbErr::RecordNotFound(_)) => "Record not found".to_string(),
+            Self::Database(_) | Self::Internal(_) => "Internal server error".to_string(),
+        }
+    }
+}
+
+impl IntoResponse for AppError {
+    fn into_response(self) -> Response {
+        match &self {
+            Self::Database(err) => error!("Database error: {}", err),
+            Self::Internal(err) => error!("Internal error: {:#}", err),
+            _ => {}
+        }
+
+        let status = self.status();
+        let mut body = json!({ "error": self.code(), "message": self.message() });
+        if let Self::Validation(fields) = &self {
+            body["fields"] = json!(fields);
+        }
+        let body = Json(body);
+
+        match self {
+            Self::TooManyRequests { retry_after, .. } => {
+                (status, [(RETRY_AFTER, retry_after.to_string())], body).into_response()
+            }
+            _ => (status, body).into_response(),
+        }
+    }
+}
+
+impl From<DbErr> for AppError {
+    fn from(err: DbErr) -> Self {
+        Self::Database(err)
+    }
+}
+
+impl From<anyhow::Error> for AppError {
+    fn from(err: anyhow::Error) -> Self {
+        Self::Internal(err)
+    }
+}
+
+impl From<MultipartError> for AppError {
+    fn from(err: MultipartError) -> Self {
+        Self::Multipart(err)
+    }
+}
+
+impl From<std::io::Error> for AppError {
+    fn from(err: std::io::Error) -> Self {
+        Self::Internal(err.into())
+    }
+}
+
+/// A request body that broke off or went over a body limit while it was read
+impl From<axum::Error> for AppError {
+    fn from(err: axum::Error) -> Self {
+        Self::BadRequest(format!("Failed to read request body: {}", err))
+    }
+}
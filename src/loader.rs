@@ -0,0 +1,55 @@
+use crate::error::AppError;
+use entity::{book_category, category, post_category};
+use sea_orm::{ColumnTrait, DatabaseConnection, EntityTrait, QueryFilter, QueryOrder};
+use std::collections::HashMap;
+use uuid::Uuid;
+
+/// Category names of every book in `book_ids`, in one query. Books without
+/// categories are missing from the map.
+pub async fn book_categories(
+    db: &DatabaseConnection,
+    book_ids: &[Uuid],
+) -> Result<HashMap<Uuid, Vec<String>>, AppError> {
+    if book_ids.is_empty() {
+        return Ok(HashMap::new());
+    }
+
+    let rows = book_category::Entity::find()
+        .find_also_related(category::Entity)
+        .filter(book_category::Column::BookId.is_in(book_ids.iter().copied()))
+        .order_by_asc(category::Column::Name)
+        .all(db)
+        .await?;
+
+    Ok(group(rows.into_iter().map(|(rel, cat)| (rel.book_id, cat))))
+}
+
+/// Category names of every post in `post_ids`, in one query. Posts without
+/// categories are missing from the map.
+pub async fn post_categories(
+    db: &DatabaseConnection,
+    post_ids: &[Uuid],
+) -> Result<HashMap<Uuid, Vec<String>>, AppError> {
+    if post_ids.is_empty() {
+        return Ok(HashMap::new());
+    }
+
+    let rows = post_category::Entity::find()
+        .find_also_related(category::Entity)
+        .filter(post_category::Column::PostId.is_in(post_ids.iter().copied()))
+        .order_by_asc(category::Column::Name)
+        .all(db)
+        .await?;
+
+    Ok(group(rows.into_iter().map(|(rel, cat)| (rel.post_id, cat))))
+}
+
+fn group(rows: impl Iterator<Item = (Uuid, Option<category::Model>)>) -> HashMap<Uuid, Vec<String>> {
+    let mut names: HashMap<Uuid, Vec<String>> = HashMap::new();
+    for (owner, cat) in rows {
+        if let Some(cat) = cat {
+            names.entry(owner).or_default().push(cat.name);
+        }
+    }
+    names
+}
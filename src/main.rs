@@ -1,8 +1,14 @@
+use crate::app::auth::permission::promote_configured_admins;
+use crate::app::auth::throttle::LoginThrottle;
+use crate::app::files::sweep::sweep_uploads;
+use crate::app::notify::notifier::notifier_from_env;
 use crate::routes::{handle_error, routes};
 use crate::utils::AppState;
 use migration::{Migrator, MigratorTrait};
 use sea_orm::{Database, ConnectOptions};
 use std::env;
+use std::net::SocketAddr;
+use std::sync::Arc;
 use std::time::Duration;
 use tokio::signal;
 use tracing::log::info;
@@ -10,9 +16,15 @@ use tracing_subscriber::{layer::SubscriberExt, util::SubscriberInitExt};
 
 mod app;
 mod controllers;
+mod dto;
+mod error;
+mod loader;
+mod pagination;
 mod respons;
 mod routes;
+mod search;
 mod utils;
+mod validation;
 
 #[tokio::main]
 async fn main() {
@@ -38,15 +50,22 @@ async fn main() {
         .await
         .expect("Could not connect to database");
     Migrator::up(&conn, None).await.unwrap();
+    promote_configured_admins(&conn)
+        .await
+        .expect("Could not promote ADMIN_USERNAMES");
 
     let app_state = AppState {
         database_connection: conn,
+        notifier: notifier_from_env(),
+        login_throttle: Arc::new(LoginThrottle::from_env()),
     };
 
+    tokio::spawn(sweep_uploads());
+
     let router = routes(app_state).merge(handle_error());
     info!("Server was run by {:?}", server_url);
     let listener = tokio::net::TcpListener::bind(&server_url).await.unwrap();
-    axum::serve(listener, router.into_make_service())
+    axum::serve(listener, router.into_make_service_with_connect_info::<SocketAddr>())
         .with_graceful_shutdown(shutdown_signal())
         .await
         .expect("Error running server");
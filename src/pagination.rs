@@ -0,0 +1,243 @@
+use crate::error::AppError;
+use crate::respons::Meta;
+use crate::validation::Validator;
+use axum::http::Uri;
+use chrono::{DateTime, Utc};
+use sea_orm::{
+    ColumnTrait, Condition, DatabaseConnection, EntityTrait, PaginatorTrait, QueryFilter,
+    QueryOrder, QuerySelect, SelectTwo,
+};
+use serde::Serialize;
+use uuid::Uuid;
+
+const DEFAULT_LIMIT: u64 = 10;
+
+/// Largest `limit` a client may ask for, `PAGINATION_MAX_LIMIT` in `.env`
+fn max_limit() -> u64 {
+    std::env::var("PAGINATION_MAX_LIMIT")
+        .ok()
+        .and_then(|v| v.parse().ok())
+        .unwrap_or(100)
+        .max(1)
+}
+
+fn clamp_limit(limit: Option<u64>) -> u64 {
+    limit.unwrap_or(DEFAULT_LIMIT).clamp(1, max_limit())
+}
+
+/// Rules for the `page`, `limit` and `cursor` query parameters
+pub fn validate_page_params(v: &mut Validator, page: Option<u64>, limit: Option<u64>, cursor: Option<&str>) {
+    if let Some(page) = page {
+        v.range("page", page, 1, u64::MAX);
+    }
+    if let Some(limit) = limit {
+        v.range("limit", limit, 1, max_limit());
+    }
+    if let Some(cursor) = cursor.filter(|cursor| !cursor.is_empty()) {
+        v.check("cursor", Cursor::decode(cursor).is_some(), "is not a valid cursor");
+    }
+    if page.is_some() && cursor.is_some() {
+        v.check("cursor", false, "can't be combined with page");
+    }
+}
+
+/// `page` and `limit` taken from the query string
+#[derive(Debug, Clone, Copy)]
+pub struct Pagination {
+    pub page: u64,
+    pub limit: u64,
+}
+
+impl Pagination {
+    pub fn new(page: Option<u64>, limit: Option<u64>) -> Self {
+        Self {
+            page: page.unwrap_or(1).max(1),
+            limit: clamp_limit(limit),
+        }
+    }
+
+    pub fn offset(&self) -> u64 {
+        (self.page - 1).saturating_mul(self.limit)
+    }
+}
+
+/// Position of the last row of a page when rows are ordered by
+/// `(created_at, id)` newest first. Sent to clients as an opaque string.
+#[derive(Debug, Clone, Copy)]
+pub struct Cursor {
+    pub created_at: DateTime<Utc>,
+    pub id: Uuid,
+}
+
+impl Cursor {
+    pub fn encode(&self) -> String {
+        hex::encode(format!("{}_{}", self.created_at.timestamp_micros(), self.id))
+    }
+
+    pub fn decode(value: &str) -> Option<Self> {
+        let raw = String::from_utf8(hex::decode(value).ok()?).ok()?;
+        let (micros, id) = raw.split_once('_')?;
+
+        Some(Self {
+            created_at: DateTime::from_timestamp_micros(micros.parse().ok()?)?,
+            id: Uuid::parse_str(id).ok()?,
+        })
+    }
+
+    /// Rows that come after this cursor
+    fn condition<C: ColumnTrait>(&self, created_at: C, id: C) -> Condition {
+        Condition::any().add(created_at.lt(self.created_at)).add(
+            Condition::all()
+                .add(created_at.eq(self.created_at))
+                .add(id.lt(self.id)),
+        )
+    }
+}
+
+/// How the client pages through a list
+#[derive(Debug, Clone, Copy)]
+pub enum PageRequest {
+    /// `?page=3&limit=20`
+    Offset(Pagination),
+    /// `?cursor=<next_cursor>&limit=20`, an empty `cursor` starts from the newest row
+    Cursor { after: Option<Cursor>, limit: u64 },
+}
+
+impl PageRequest {
+    pub fn new(page: Option<u64>, limit: Option<u64>, cursor: Option<&str>) -> Result<Self, AppError> {
+        let Some(raw) = cursor else {
+            return Ok(Self::Offset(Pagination::new(page, limit)));
+        };
+
+        let after = if raw.is_empty() {
+            None
+        } else {
+            Some(Cursor::decode(raw).ok_or_else(|| AppError::bad_request("Invalid cursor"))?)
+        };
+        Ok(Self::Cursor {
+            after,
+            limit: clamp_limit(limit),
+        })
+    }
+
+    /// `meta` for a list that is known to be empty
+    pub fn empty_meta(&self, uri: &Uri) -> Meta {
+        match *self {
+            Self::Offset(pagination) => PageMeta::new(uri, pagination, 0).into(),
+            Self::Cursor { limit, .. } => CursorMeta::new(uri, limit, None).into(),
+        }
+    }
+}
+
+/// Pagination details of a page-mode list response
+#[derive(Debug, Serialize)]
+pub struct PageMeta {
+    pub page: u64,
+    pub limit: u64,
+    pub total: u64,
+    pub total_pages: u64,
+    pub next: Option<String>,
+    pub prev: Option<String>,
+}
+
+impl PageMeta {
+    /// `uri` is the request URI, the links keep its query and only swap `page`
+    pub fn new(uri: &Uri, pagination: Pagination, total: u64) -> Self {
+        let Pagination { page, limit } = pagination;
+        let total_pages = total.div_ceil(limit);
+
+        let next = (page < total_pages).then(|| link(uri, "page", &(page + 1).to_string()));
+        let prev = (page > 1).then(|| link(uri, "page", &(page - 1).to_string()));
+
+        Self {
+            page,
+            limit,
+            total,
+            total_pages,
+            next,
+            prev,
+        }
+    }
+}
+
+/// Pagination details of a cursor-mode list response
+#[derive(Debug, Serialize)]
+pub struct CursorMeta {
+    pub limit: u64,
+    pub next_cursor: Option<String>,
+    pub next: Option<String>,
+}
+
+impl CursorMeta {
+    pub fn new(uri: &Uri, limit: u64, next: Option<Cursor>) -> Self {
+        let next_cursor = next.map(|cursor| cursor.encode());
+        let next = next_cursor.as_deref().map(|cursor| link(uri, "cursor", cursor));
+
+        Self {
+            limit,
+            next_cursor,
+            next,
+        }
+    }
+}
+
+/// `uri` with its `page`/`cursor` parameter replaced by `key=value`
+fn link(uri: &Uri, key: &str, value: &str) -> String {
+    let mut query = uri
+        .query()
+        .unwrap_or_default()
+        .split('&')
+        .filter(|pair| !pair.is_empty() && !matches!(pair.split('=').next(), Some("page" | "cursor")))
+        .map(str::to_string)
+        .collect::<Vec<_>>();
+    query.push(format!("{}={}", key, value));
+
+    format!("{}?{}", uri.path(), query.join("&"))
+}
+
+/// Load one page of `query`, newest first after any order `query` already has.
+/// Page mode runs a count query for `meta`, cursor mode reads one extra row to
+/// know whether there is a next page.
+pub async fn fetch_page<E, F>(
+    db: &DatabaseConnection,
+    query: SelectTwo<E, F>,
+    request: PageRequest,
+    uri: &Uri,
+    (created_at, id): (E::Column, E::Column),
+    cursor_of: impl Fn(&E::Model) -> Cursor,
+) -> Result<(Vec<(E::Model, Option<F::Model>)>, Meta), AppError>
+where
+    E: EntityTrait,
+    F: EntityTrait,
+    E::Model: Sync,
+    F::Model: Sync,
+{
+    let query = query.order_by_desc(created_at).order_by_desc(id);
+
+    match request {
+        PageRequest::Offset(pagination) => {
+            let total = query.clone().count(db).await?;
+            let rows = query
+                .offset(pagination.offset())
+                .limit(pagination.limit)
+                .all(db)
+                .await?;
+            Ok((rows, PageMeta::new(uri, pagination, total).into()))
+        }
+        PageRequest::Cursor { after, limit } => {
+            let query = match after {
+                Some(cursor) => query.filter(cursor.condition(created_at, id)),
+                None => query,
+            };
+
+            let mut rows = query.limit(limit + 1).all(db).await?;
+            let next = if rows.len() as u64 > limit {
+                rows.truncate(limit as usize);
+                rows.last().map(|(model, _)| cursor_of(model))
+            } else {
+                None
+            };
+            Ok((rows, CursorMeta::new(uri, limit, next).into()))
+        }
+    }
+}
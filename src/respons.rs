@@ -1,34 +1,80 @@
-use axum::response::IntoResponse;
+use crate::error::AppError;
+use crate::pagination::{CursorMeta, PageMeta};
 use axum::Json;
 use serde::Serialize;
-use serde_json::{json, Value};
+use serde_json::Value;
 
-pub fn api_response<T>(items: Vec<T>) -> impl IntoResponse
+/// The one shape every successful response has
+#[derive(Debug, Serialize)]
+pub struct ApiResponse<T> {
+    pub data: T,
+    pub message: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub meta: Option<Meta>,
+}
+
+/// `meta` of a list response, depending on how the client paginates
+#[derive(Debug, Serialize)]
+#[serde(untagged)]
+pub enum Meta {
+    Page(PageMeta),
+    Cursor(CursorMeta),
+}
+
+impl From<PageMeta> for Meta {
+    fn from(meta: PageMeta) -> Self {
+        Self::Page(meta)
+    }
+}
+
+impl From<CursorMeta> for Meta {
+    fn from(meta: CursorMeta) -> Self {
+        Self::Cursor(meta)
+    }
+}
+
+fn envelope<T>(data: T, message: &str, meta: Option<Meta>) -> Result<Json<Value>, AppError>
 where
     T: Serialize,
 {
-    let list = items
-        .into_iter()
-        .map(|item| serde_json::to_value(item).unwrap())
-        .collect::<Vec<Value>>();
+    let response = ApiResponse {
+        data,
+        message: message.to_string(),
+        meta,
+    };
+    let value = serde_json::to_value(response).map_err(anyhow::Error::from)?;
+
+    Ok(Json(value))
+}
 
-    let message = if list.is_empty() {
+fn list_message<T>(items: &[T]) -> &'static str {
+    if items.is_empty() {
         "No data"
     } else {
         "Ok"
-    };
+    }
+}
 
-    Json(json!({
-        "data": list,
-        "message": message
-    }))
+pub fn api_response<T>(items: Vec<T>) -> Result<Json<Value>, AppError>
+where
+    T: Serialize,
+{
+    let message = list_message(&items);
+    envelope(items, message, None)
 }
 
-pub fn api_response_single<T>(item: T) -> impl IntoResponse
+/// A list response with pagination `meta`
+pub fn api_response_paged<T>(items: Vec<T>, meta: impl Into<Meta>) -> Result<Json<Value>, AppError>
 where
     T: Serialize,
 {
-    let value = serde_json::to_value(item).unwrap();
+    let message = list_message(&items);
+    envelope(items, message, Some(meta.into()))
+}
 
-    Json(value)
+pub fn api_response_single<T>(item: T) -> Result<Json<Value>, AppError>
+where
+    T: Serialize,
+{
+    envelope(item, "Ok", None)
 }
@@ -1,20 +1,66 @@
+use crate::app::auth::permission::{require, Permission};
+use crate::app::files::policy::{UploadKind, UploadPolicy};
+use crate::app::middleware::panic::{handle_panic, panic_response};
+use crate::app::middleware::tus::tus_resumable;
+use crate::controllers::api_key_controller::{create_key, delete_api_key, list_api_keys};
+use crate::controllers::auth_controller::{
+    forgot_password, logout, refresh, reset_password, two_factor_confirm, two_factor_disable,
+    two_factor_setup, two_factor_verify,
+};
 use crate::controllers::post_controller::*;
 use crate::controllers::user_controller::*;
 use crate::utils::AppState;
-use axum::http::StatusCode;
-use axum::routing::{get, post};
+use crate::error::AppError;
+use axum::extract::DefaultBodyLimit;
+use axum::middleware::{from_fn, from_fn_with_state};
+use axum::routing::{delete, get, head, post, put};
 use axum::Router;
 use std::sync::Arc;
+use tower_http::catch_panic::CatchPanicLayer;
+use tower_http::request_id::{MakeRequestUuid, PropagateRequestIdLayer, SetRequestIdLayer};
 use tower_http::trace::TraceLayer;
-use crate::controllers::book_controller::{create_book, get_book, list_books};
-use crate::controllers::category_controller::{create_category, list_categories};
+use crate::controllers::book_controller::{create_book, delete_book, get_book, list_books, update_book};
+use crate::controllers::category_controller::{create_category, delete_category, list_categories, update_category};
 use crate::controllers::file_upload_controller::upload;
+use crate::controllers::search_controller::search;
+use crate::controllers::tus_controller::{tus_create, tus_delete, tus_head, tus_options, tus_patch};
+use crate::controllers::upload_session_controller::{
+    cancel_upload, complete_upload, init_upload, put_chunk, upload_status,
+};
 
 pub fn routes(state: AppState) -> Router {
+    let state = Arc::new(state);
+
+    // Multipart bodies are capped by these layers, raw bodies (session chunks,
+    // tus PATCH) by their handlers while they stream
+    let book_policy = UploadPolicy::for_kind(UploadKind::Book);
+    let file_policy = UploadPolicy::for_kind(UploadKind::File);
+
     let book_routes = Router::new()
         .route("/", get(list_books))
-        .route("/{title}", get(get_book))
-        .route("/upload", post(create_book));
+        .route("/{title}", get(get_book).put(update_book).delete(delete_book))
+        // Room for one chunk plus the text fields, chunkData itself is capped while it streams
+        .route("/upload", post(create_book).layer(DefaultBodyLimit::max(book_policy.body_limit())));
+
+    // tus 1.0 resumable uploads, see https://tus.io/protocols/resumable-upload
+    let tus_routes = Router::new()
+        .route("/", post(tus_create).options(tus_options))
+        .route("/{id}", head(tus_head).patch(tus_patch).delete(tus_delete))
+        .layer(from_fn(tus_resumable));
+
+    // Routes below are only reachable with the matching permission
+    let category_admin_routes = Router::new()
+        .route("/categories", post(create_category))
+        .route("/categories/{slug}", put(update_category).delete(delete_category))
+        .route_layer(from_fn_with_state(state.clone(), require(Permission::ManageCategories)));
+
+    let user_admin_routes = Router::new()
+        .route("/users", get(list_users))
+        .route_layer(from_fn_with_state(state.clone(), require(Permission::ListUsers)));
+
+    let role_admin_routes = Router::new()
+        .route("/users/{id}/role", put(update_user_role))
+        .route_layer(from_fn_with_state(state.clone(), require(Permission::ManageRoles)));
 
     Router::new()
         .route("/", get(|| async { "hello world" }))
@@ -23,29 +69,43 @@ pub fn routes(state: AppState) -> Router {
             "/post/{slug}",
             get(get_post).put(update_post).delete(delete_post),
         )
-        .route("/users", get(list_users).post(create_user))
+        .route("/post/{slug}/attachments", get(list_post_attachments))
+        .route("/users", post(create_user))
+        .route("/users/me", get(get_me))
+        .route("/users/me/password", put(change_password))
+        .route("/users/me/api-keys", get(list_api_keys).post(create_key))
+        .route("/users/me/api-keys/{id}", delete(delete_api_key))
         .route("/users/creds", post(get_user_credentials))
-        .route("/categories", post(create_category).get(list_categories))
-        .route("/upload", post(upload))
+        .route("/auth/login", post(get_user_credentials))
+        .route("/auth/refresh", post(refresh))
+        .route("/auth/logout", post(logout))
+        .route("/auth/password/forgot", post(forgot_password))
+        .route("/auth/password/reset", post(reset_password))
+        .route("/auth/2fa/setup", post(two_factor_setup))
+        .route("/auth/2fa/confirm", post(two_factor_confirm))
+        .route("/auth/2fa/disable", post(two_factor_disable))
+        .route("/auth/2fa/verify", post(two_factor_verify))
+        .route("/categories", get(list_categories))
+        .route("/search", get(search))
+        .route("/upload", post(upload).layer(DefaultBodyLimit::max(file_policy.body_limit())))
+        .route("/uploads", post(init_upload))
+        .route("/uploads/{id}", get(upload_status).delete(cancel_upload))
+        .route("/uploads/{id}/chunks/{index}", put(put_chunk))
+        .route("/uploads/{id}/complete", post(complete_upload))
         .nest("/book", book_routes)
+        .nest("/files", tus_routes)
+        .merge(category_admin_routes)
+        .merge(user_admin_routes)
+        .merge(role_admin_routes)
         // Layer
+        .layer(CatchPanicLayer::custom(handle_panic))
+        .layer(from_fn(panic_response))
         .layer(TraceLayer::new_for_http())
-        .with_state(Arc::new(state))
+        .layer(PropagateRequestIdLayer::x_request_id())
+        .layer(SetRequestIdLayer::x_request_id(MakeRequestUuid))
+        .with_state(state)
 }
 
 pub fn handle_error() -> Router {
-    Router::new().fallback(get(not_found_error("404")))
-}
-
-/* Map any error into a `500 Internal Server Error` */
-pub fn internal_error<E>(err: E) -> (StatusCode, String)
-where
-    E: ToString,
-{
-    (StatusCode::INTERNAL_SERVER_ERROR, err.to_string())
-}
-
-// Map str into a `404 Internal Server Error`
-pub fn not_found_error(msg: &str) -> (StatusCode, String) {
-    (StatusCode::NOT_FOUND, format!("{msg:?}"))
+    Router::new().fallback(get(|| async { AppError::not_found("Route not found") }))
 }
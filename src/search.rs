@@ -0,0 +1,112 @@
+use crate::error::AppError;
+use entity::post;
+use sea_orm::sea_query::{Alias, Expr, Func, LikeExpr, Query, SimpleExpr};
+use sea_orm::{ColumnTrait, Condition, ConnectionTrait, DatabaseConnection, DbBackend};
+use std::collections::HashMap;
+use uuid::Uuid;
+
+/// `keyword` lowercased, with `%`, `_` and `\` escaped for LIKE
+fn escape_like(keyword: &str) -> String {
+    keyword
+        .to_lowercase()
+        .replace('\\', "\\\\")
+        .replace('%', "\\%")
+        .replace('_', "\\_")
+}
+
+/// `keyword` as a LIKE pattern that matches it anywhere, with `%`, `_` and
+/// `\` taken literally
+pub fn contains_pattern(keyword: &str) -> LikeExpr {
+    LikeExpr::new(format!("%{}%", escape_like(keyword))).escape('\\')
+}
+
+fn lower<C: ColumnTrait>(column: C) -> Expr {
+    Expr::expr(Func::lower(Expr::col((column.entity_name(), column))))
+}
+
+/// `LOWER(column) LIKE '%keyword%'`, works on PostgreSQL and SQLite alike
+pub fn contains_insensitive<C: ColumnTrait>(column: C, keyword: &str) -> SimpleExpr {
+    lower(column).like(contains_pattern(keyword))
+}
+
+/// Sort key for LIKE matches, ignoring case: 0 when `column` is `keyword`,
+/// 1 when it starts with it, 2 otherwise
+pub fn match_rank<C: ColumnTrait>(column: C, keyword: &str) -> SimpleExpr {
+    let prefix = LikeExpr::new(format!("{}%", escape_like(keyword))).escape('\\');
+    Expr::case(lower(column).eq(keyword.to_lowercase()), 0)
+        .case(lower(column).like(prefix), 1)
+        .finally(2)
+        .into()
+}
+
+/// Rows where any of `columns` contains `keyword`, ignoring case
+pub fn any_contains<C: ColumnTrait>(columns: &[C], keyword: &str) -> Condition {
+    columns
+        .iter()
+        .fold(Condition::any(), |condition, column| {
+            condition.add(contains_insensitive(*column, keyword))
+        })
+}
+
+/// Whether `post.search_vector` exists, see the add_search_vector_to_post migration
+pub fn full_text_available(db: &DatabaseConnection) -> bool {
+    db.get_database_backend() == DbBackend::Postgres
+}
+
+/// Posts whose title or text match `keyword`, read like a web search box:
+/// words, "quoted phrases", `or` and `-excluded`
+pub fn post_matches(keyword: &str) -> SimpleExpr {
+    Expr::cust_with_values(
+        "post.search_vector @@ websearch_to_tsquery('simple', $1)",
+        [keyword],
+    )
+}
+
+/// Relevance of a post for `keyword`, title words weigh more than text words
+pub fn post_rank(keyword: &str) -> SimpleExpr {
+    Expr::cust_with_values(
+        "ts_rank(post.search_vector, websearch_to_tsquery('simple', $1))",
+        [keyword],
+    )
+}
+
+/// Post text with `& < > " '` turned into entities, so the only markup in a
+/// snippet is the `<b>` that `ts_headline` adds
+const ESCAPED_POST_TEXT: &str = "replace(replace(replace(replace(replace(coalesce(text, ''), \
+     '&', '&amp;'), '<', '&lt;'), '>', '&gt;'), '\"', '&quot;'), '''', '&#39;')";
+
+/// Parts of the text of `post_ids` around the words of `keyword`. The text is
+/// HTML-escaped before highlighting and matches are wrapped in `<b>`, so a
+/// snippet can be inserted as HTML as it is.
+pub async fn post_snippets(
+    db: &DatabaseConnection,
+    keyword: &str,
+    post_ids: &[Uuid],
+) -> Result<HashMap<Uuid, String>, AppError> {
+    if post_ids.is_empty() {
+        return Ok(HashMap::new());
+    }
+
+    let query = Query::select()
+        .column(post::Column::Id)
+        .expr_as(
+            Expr::cust_with_values(
+                format!(
+                    "ts_headline('simple', {}, websearch_to_tsquery('simple', $1), \
+                     'MaxFragments=2, MinWords=5, MaxWords=20')",
+                    ESCAPED_POST_TEXT
+                ),
+                [keyword],
+            ),
+            Alias::new("snippet"),
+        )
+        .from(post::Entity)
+        .and_where(post::Column::Id.is_in(post_ids.iter().copied()))
+        .to_owned();
+
+    let mut snippets = HashMap::with_capacity(post_ids.len());
+    for row in db.query_all(db.get_database_backend().build(&query)).await? {
+        snippets.insert(row.try_get::<Uuid>("", "id")?, row.try_get::<String>("", "snippet")?);
+    }
+    Ok(snippets)
+}
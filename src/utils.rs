@@ -1,9 +1,14 @@
+use crate::app::auth::throttle::LoginThrottle;
+use crate::app::notify::notifier::Notifier;
 use regex::Regex;
 use sea_orm::DatabaseConnection;
+use std::sync::Arc;
 
 #[derive(Clone)]
 pub struct AppState {
     pub database_connection: DatabaseConnection,
+    pub notifier: Arc<dyn Notifier>,
+    pub login_throttle: Arc<LoginThrottle>,
 }
 
 pub fn slugify(text: &str) -> String {
@@ -0,0 +1,160 @@
+use crate::error::AppError;
+use axum::extract::rejection::JsonRejection;
+use axum::extract::{FromRequest, FromRequestParts, Request};
+use axum::http::request::Parts;
+use axum::Json;
+use regex::Regex;
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use std::fmt::Display;
+use std::sync::LazyLock;
+
+pub static USERNAME_PATTERN: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r"^[A-Za-z0-9_.-]+$").expect("valid username pattern"));
+pub static SLUG_PATTERN: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r"^[a-z0-9]+(?:-[a-z0-9]+)*$").expect("valid slug pattern"));
+
+/// Why one field was rejected
+#[derive(Debug, Clone, Serialize)]
+pub struct FieldError {
+    pub field: String,
+    pub message: String,
+}
+
+/// Collects every failed rule instead of stopping at the first one
+#[derive(Debug, Default)]
+pub struct Validator {
+    errors: Vec<FieldError>,
+}
+
+impl Validator {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Reject `field` with `message` unless `ok` holds
+    pub fn check(&mut self, field: &str, ok: bool, message: impl Into<String>) -> &mut Self {
+        if !ok {
+            self.errors.push(FieldError {
+                field: field.to_string(),
+                message: message.into(),
+            });
+        }
+        self
+    }
+
+    /// Length in characters, both ends included
+    pub fn length(&mut self, field: &str, value: &str, min: usize, max: usize) -> &mut Self {
+        let len = value.chars().count();
+        self.check(
+            field,
+            (min..=max).contains(&len),
+            format!("must be between {} and {} characters", min, max),
+        )
+    }
+
+    /// Both ends included
+    pub fn range<T>(&mut self, field: &str, value: T, min: T, max: T) -> &mut Self
+    where
+        T: PartialOrd + Display,
+    {
+        let ok = value >= min && value <= max;
+        self.check(field, ok, format!("must be between {} and {}", min, max))
+    }
+
+    /// `description` tells the client what the pattern allows
+    pub fn pattern(&mut self, field: &str, value: &str, pattern: &Regex, description: &str) -> &mut Self {
+        self.check(field, pattern.is_match(value), format!("must contain only {}", description))
+    }
+
+    pub fn one_of(&mut self, field: &str, value: &str, allowed: &[&str]) -> &mut Self {
+        self.check(
+            field,
+            allowed.contains(&value),
+            format!("must be one of: {}", allowed.join(", ")),
+        )
+    }
+
+    pub fn finish(&mut self) -> Result<(), AppError> {
+        if self.errors.is_empty() {
+            Ok(())
+        } else {
+            Err(AppError::Validation(std::mem::take(&mut self.errors)))
+        }
+    }
+}
+
+pub trait Validate {
+    fn validate(&self) -> Result<(), AppError>;
+}
+
+/// `Json<T>` that also runs `T::validate`
+pub struct ValidatedJson<T>(pub T);
+
+impl<S, T> FromRequest<S> for ValidatedJson<T>
+where
+    S: Send + Sync,
+    T: DeserializeOwned + Validate,
+{
+    type Rejection = AppError;
+
+    async fn from_request(req: Request, state: &S) -> Result<Self, Self::Rejection> {
+        let Json(value) = Json::<T>::from_request(req, state)
+            .await
+            .map_err(|rejection| match rejection {
+                JsonRejection::MissingJsonContentType(err) => AppError::UnsupportedMediaType(err.body_text()),
+                other => AppError::bad_request(other.body_text()),
+            })?;
+        value.validate()?;
+        Ok(Self(value))
+    }
+}
+
+/// `Query<T>` that names every bad parameter and runs `T::validate`.
+/// `T` should only have `Option` fields, so each parameter can be checked on its own.
+pub struct ValidatedQuery<T>(pub T);
+
+impl<S, T> FromRequestParts<S> for ValidatedQuery<T>
+where
+    S: Send + Sync,
+    T: DeserializeOwned + Validate,
+{
+    type Rejection = AppError;
+
+    async fn from_request_parts(parts: &mut Parts, _state: &S) -> Result<Self, Self::Rejection> {
+        let query = parts.uri.query().unwrap_or_default();
+
+        let value = match serde_urlencoded::from_str::<T>(query) {
+            Ok(value) => value,
+            Err(_) => return Err(AppError::Validation(query_errors::<T>(query))),
+        };
+        value.validate()?;
+        Ok(Self(value))
+    }
+}
+
+/// Parse every parameter alone to find out which ones failed
+fn query_errors<T: DeserializeOwned>(query: &str) -> Vec<FieldError> {
+    let errors = query
+        .split('&')
+        .filter(|pair| !pair.is_empty())
+        .filter_map(|pair| {
+            let err = serde_urlencoded::from_str::<T>(pair).err()?;
+            let field = pair.split('=').next().unwrap_or_default();
+            Some(FieldError {
+                field: urlencoding::decode(field).map(|f| f.into_owned()).unwrap_or_else(|_| field.to_string()),
+                message: format!("is invalid: {}", err),
+            })
+        })
+        .collect::<Vec<_>>();
+
+    if errors.is_empty() {
+        // Only a combination of parameters fails, e.g. a repeated one
+        vec![FieldError {
+            field: "query".to_string(),
+            message: "is invalid".to_string(),
+        }]
+    } else {
+        errors
+    }
+}